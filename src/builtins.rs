@@ -0,0 +1,216 @@
+//! The primitive procedures every interpreter starts out with. Special forms,
+//! which need their arguments unevaluated, live in `eval` instead.
+
+use std::cmp::Ordering;
+
+use super::{Expr, Expression, Number, Atom, List, Str, Num};
+use error::{Error, Arity};
+use eval::{self, EvalResult};
+use interp::Interpreter;
+use syntax;
+
+/// Registers every builtin procedure with `interp`.
+pub fn register_all(interp: &mut Interpreter) {
+    interp.register("atom", Arity::Exactly(1), atom);
+    interp.register("eq", Arity::Exactly(2), eq);
+    interp.register("first", Arity::Exactly(1), first);
+    interp.register("rest", Arity::Exactly(1), rest);
+    interp.register("cons", Arity::Exactly(2), cons);
+    interp.register("load", Arity::Exactly(1), load);
+    interp.register("macroexpand", Arity::Exactly(1), macroexpand);
+    interp.register("macroexpand-1", Arity::Exactly(1), macroexpand_1);
+
+    interp.register("+", Arity::AtLeast(0), add);
+    interp.register("-", Arity::AtLeast(1), sub);
+    interp.register("*", Arity::AtLeast(0), mul);
+    interp.register("/", Arity::AtLeast(1), div);
+    interp.register("quotient", Arity::Exactly(2), quotient);
+    interp.register("mod", Arity::Exactly(2), modulo);
+    interp.register("numerator", Arity::Exactly(1), numerator);
+    interp.register("denominator", Arity::Exactly(1), denominator);
+    interp.register("exact->inexact", Arity::Exactly(1), exact_to_inexact);
+
+    interp.register("<", Arity::AtLeast(1), less);
+    interp.register(">", Arity::AtLeast(1), greater);
+    interp.register("<=", Arity::AtLeast(1), less_eq);
+    interp.register(">=", Arity::AtLeast(1), greater_eq);
+    interp.register("=", Arity::AtLeast(1), num_eq);
+}
+
+fn atom(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    Ok( truth(!args[0].is_list() || args[0].is_empty_list()) )
+}
+
+fn eq(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let (val1, val2) = (&args[0], &args[1]);
+    Ok( truth((val1.is_empty_list() && val2.is_empty_list())
+              || (!val1.is_list() && val1.eq(val2))) )
+}
+
+fn first(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    match args[0] {
+        List(ref list) if !list.is_empty() => Ok( list[0].clone() ),
+        ref val => Err(type_error("first", "a non-empty list", val)),
+    }
+}
+
+fn rest(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    match args[0] {
+        List(ref list) if !list.is_empty() => Ok( List(list[1..].to_vec()) ),
+        ref val => Err(type_error("rest", "a non-empty list", val)),
+    }
+}
+
+fn cons(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    match args[1] {
+        List(ref list) => {
+            let mut list = list.clone();
+            list.insert(0, args[0].clone());
+            Ok( List(list) )
+        },
+        ref val => Err(type_error("cons", "a list as its second argument", val)),
+    }
+}
+
+/// Evaluates the file at the given path, returning the value of its last
+/// expression.
+fn load(interp: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    match args[0] {
+        Str(ref path) => interp.eval_file(path),
+        ref val => Err(type_error("load", "a string", val)),
+    }
+}
+
+// The expansions of hygienic macros are shown with the symbols they introduced
+// under their original names, for readability.
+
+/// Expands a macro call repeatedly, until it isn't one any more.
+fn macroexpand(interp: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let env = interp.env().clone();
+    Ok( syntax::strip(&eval::macroexpand(interp, &env, &args[0])?) )
+}
+
+/// Expands a macro call once. Anything else is returned as it is.
+fn macroexpand_1(interp: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let env = interp.env().clone();
+    let expansion = eval::macroexpand_1(interp, &env, &args[0])?;
+    Ok( syntax::strip(&expansion.unwrap_or_else(|| args[0].clone())) )
+}
+
+fn add(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = numbers("+", args)?;
+    Ok( Num(nums.iter().fold(Number::Int(0), |acc, n| acc.add(n))) )
+}
+
+fn sub(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = numbers("-", args)?;
+    if nums.len() == 1 {
+        return Ok( Num(Number::Int(0).sub(&nums[0])) );
+    }
+    Ok( Num(nums[1..].iter().fold(nums[0].clone(), |acc, n| acc.sub(n))) )
+}
+
+fn mul(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = numbers("*", args)?;
+    Ok( Num(nums.iter().fold(Number::Int(1), |acc, n| acc.mul(n))) )
+}
+
+fn div(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = numbers("/", args)?;
+    let (mut acc, rest) = if nums.len() == 1 {
+        (Number::Int(1), &nums[..])
+    } else {
+        (nums[0].clone(), &nums[1..])
+    };
+    for n in rest {
+        acc = acc.div(n).ok_or_else(|| division_by_zero("/", args))?;
+    }
+    Ok( Num(acc) )
+}
+
+fn quotient(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = integers("quotient", args)?;
+    nums[0].quotient(&nums[1]).map(Num).ok_or_else(|| division_by_zero("quotient", args))
+}
+
+fn modulo(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = integers("mod", args)?;
+    nums[0].modulo(&nums[1]).map(Num).ok_or_else(|| division_by_zero("mod", args))
+}
+
+fn numerator(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    Ok( Num(exact("numerator", &args[0])?.numerator()) )
+}
+
+fn denominator(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    Ok( Num(exact("denominator", &args[0])?.denominator()) )
+}
+
+fn exact_to_inexact(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    let nums = numbers("exact->inexact", args)?;
+    Ok( Num(Number::Float(nums[0].to_f64())) )
+}
+
+fn less(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    compare("<", args, |o| o == Ordering::Less)
+}
+
+fn greater(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    compare(">", args, |o| o == Ordering::Greater)
+}
+
+fn less_eq(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    compare("<=", args, |o| o != Ordering::Greater)
+}
+
+fn greater_eq(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    compare(">=", args, |o| o != Ordering::Less)
+}
+
+fn num_eq(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    compare("=", args, |o| o == Ordering::Equal)
+}
+
+/// Checks that each consecutive pair of arguments is ordered as `holds` wants.
+fn compare(name: &str, args: &[Expr], holds: fn(Ordering) -> bool) -> EvalResult {
+    let nums = numbers(name, args)?;
+    Ok( truth(nums.windows(2).all(|w| w[0].compare(&w[1]).is_some_and(holds))) )
+}
+
+fn numbers(name: &str, args: &[Expr]) -> Result<Vec<Number>, Error> {
+    args.iter().map(|arg| match *arg {
+        Num(ref n) => Ok(n.clone()),
+        ref val => Err(type_error(name, "numbers", val)),
+    }).collect()
+}
+
+fn integers(name: &str, args: &[Expr]) -> Result<Vec<Number>, Error> {
+    let nums = numbers(name, args)?;
+    match nums.iter().find(|n| !n.is_integer()) {
+        Some(n) => Err(type_error(name, "integers", &Num(n.clone()))),
+        None => Ok(nums),
+    }
+}
+
+fn exact(name: &str, arg: &Expr) -> Result<Number, Error> {
+    match *arg {
+        Num(ref n) if n.is_exact() => Ok(n.clone()),
+        ref val => Err(type_error(name, "an exact number", val)),
+    }
+}
+
+fn truth(b: bool) -> Expr {
+    if b { Atom("t".to_string()) } else { Expression::empty_list() }
+}
+
+fn type_error(builtin: &str, expected: &'static str, val: &Expr) -> Error {
+    Error::WrongType { builtin: builtin.to_string(), expected, expr: val.clone() }
+}
+
+// Builtins only see the values of their arguments, so the offending call is
+// rebuilt from those.
+fn division_by_zero(name: &str, args: &[Expr]) -> Error {
+    let mut call = vec!(Atom(name.to_string()));
+    call.extend(args.iter().cloned());
+    Error::DivisionByZero(List(call))
+}
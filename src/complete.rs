@@ -0,0 +1,106 @@
+//! Tab completion for the REPL: special forms, bound symbols, and the paths
+//! of files to `load`.
+
+use std::fs;
+
+use radicle::Env;
+use radicle::eval::SPECIAL_FORMS;
+use radicle::read::is_delimiter;
+
+use editor::Completer;
+
+/// Completes symbols to special forms and to what is bound in an
+/// environment, and strings in a call to `load` to file paths.
+pub struct EnvCompleter {
+    env: Env,
+}
+
+impl EnvCompleter {
+    /// Creates a completer for the symbols bound in `env`. Environments share
+    /// their frames with their clones, so later definitions are offered too.
+    pub fn new(env: Env) -> EnvCompleter {
+        EnvCompleter { env }
+    }
+
+    fn complete_symbol(&self, prefix: &str) -> Vec<String> {
+        if prefix.is_empty() {
+            return vec!();
+        }
+
+        let mut names: Vec<String> = SPECIAL_FORMS.iter().map(|&(s, _)| s.to_string())
+                                                  .chain(self.env.names())
+                                                  .filter(|name| name.starts_with(prefix))
+                                                  .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+impl Completer for EnvCompleter {
+    fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        match string_start(before) {
+            Some(start) if is_load_call(&before[..start - 1]) => (start, complete_path(&before[start..])),
+            Some(_) => (pos, vec!()),
+            None => {
+                let start = before.char_indices().rev()
+                                  .find(|&(_, c)| c.is_whitespace() || c == '"' || is_delimiter(c))
+                                  .map(|(i, c)| i + c.len_utf8())
+                                  .unwrap_or(0);
+                (start, self.complete_symbol(&before[start..]))
+            },
+        }
+    }
+}
+
+/// If `s` ends inside a string, where the contents of that string start.
+fn string_start(s: &str) -> Option<usize> {
+    let mut start = None;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if start.is_some() && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            start = if start.is_some() { None } else { Some(i + 1) };
+        }
+    }
+    start
+}
+
+/// Whether a string that follows `s` is the argument of a call to `load`.
+fn is_load_call(s: &str) -> bool {
+    match s.trim_end().strip_suffix("load") {
+        Some(rest) => rest.trim_end().ends_with(['(', '[', '{']),
+        None => false,
+    }
+}
+
+/// The files and directories whose paths start with `prefix`. Directories
+/// are given a trailing slash, and hidden files are only offered if
+/// `prefix` names one.
+fn complete_path(prefix: &str) -> Vec<String> {
+    let (dir, file) = match prefix.rfind('/') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    };
+
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return vec!(),
+    };
+
+    let mut paths: Vec<String> = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+            return None;
+        }
+        let is_dir = fs::metadata(entry.path()).map(|m| m.is_dir()).unwrap_or(false);
+        Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+    }).collect();
+    paths.sort();
+    paths
+}
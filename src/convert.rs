@@ -0,0 +1,118 @@
+//! Conversions between Rust values and Lisp expressions, for code that
+//! embeds radicle.
+//!
+//! Converting into an expression always succeeds. Converting out of one
+//! fails with `Error::Conversion` if the expression is of the wrong kind.
+
+use std::convert::TryFrom;
+
+use super::{Expr, Expression, Number, Atom, List, Str, Num};
+use error::Error;
+
+impl From<i64> for Expr {
+    fn from(i: i64) -> Expr {
+        Num(Number::Int(i))
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(f: f64) -> Expr {
+        Num(Number::Float(f))
+    }
+}
+
+impl From<Number> for Expr {
+    fn from(n: Number) -> Expr {
+        Num(n)
+    }
+}
+
+impl From<String> for Expr {
+    fn from(s: String) -> Expr {
+        Str(s)
+    }
+}
+
+impl<'a> From<&'a str> for Expr {
+    fn from(s: &'a str) -> Expr {
+        Str(s.to_string())
+    }
+}
+
+/// `true` becomes `t` and `false` becomes `()`.
+impl From<bool> for Expr {
+    fn from(b: bool) -> Expr {
+        if b { Atom("t".to_string()) } else { Expression::empty_list() }
+    }
+}
+
+impl From<Vec<Expr>> for Expr {
+    fn from(v: Vec<Expr>) -> Expr {
+        List(v)
+    }
+}
+
+/// Only integers that fit in an `i64` convert to one.
+impl TryFrom<Expr> for i64 {
+    type Error = Error;
+
+    fn try_from(expr: Expr) -> Result<i64, Error> {
+        match expr {
+            Num(Number::Int(i)) => Ok(i),
+            _ => Err(conversion_error("i64", expr)),
+        }
+    }
+}
+
+/// Any number converts to an `f64`, possibly losing precision.
+impl TryFrom<Expr> for f64 {
+    type Error = Error;
+
+    fn try_from(expr: Expr) -> Result<f64, Error> {
+        match expr {
+            Num(ref n) => Ok(n.to_f64()),
+            _ => Err(conversion_error("f64", expr)),
+        }
+    }
+}
+
+impl TryFrom<Expr> for String {
+    type Error = Error;
+
+    fn try_from(expr: Expr) -> Result<String, Error> {
+        match expr {
+            Str(s) => Ok(s),
+            _ => Err(conversion_error("String", expr)),
+        }
+    }
+}
+
+/// `t` converts to `true` and `()` to `false`.
+impl TryFrom<Expr> for bool {
+    type Error = Error;
+
+    fn try_from(expr: Expr) -> Result<bool, Error> {
+        if expr.is_empty_list() {
+            Ok(false)
+        } else if expr.eq(&Atom("t".to_string())) {
+            Ok(true)
+        } else {
+            Err(conversion_error("bool", expr))
+        }
+    }
+}
+
+impl TryFrom<Expr> for Vec<Expr> {
+    type Error = Error;
+
+    fn try_from(expr: Expr) -> Result<Vec<Expr>, Error> {
+        match expr {
+            List(v) => Ok(v),
+            _ => Err(conversion_error("Vec<Expr>", expr)),
+        }
+    }
+}
+
+fn conversion_error(to: &'static str, expr: Expr) -> Error {
+    Error::Conversion { to, expr }
+}
@@ -0,0 +1,538 @@
+//! A line editor for the REPL, with cursor movement, history and reverse
+//! search. Editing needs a terminal in raw mode, which is only supported on
+//! Unix; elsewhere, or when stdin or stdout is not a terminal, lines are read
+//! as they come.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// How many lines of history are kept, both in memory and in the history
+/// file.
+pub const HISTORY_SIZE: usize = 1000;
+
+/// What `Editor::read_line` got from the user.
+#[derive(PartialEq, Debug)]
+pub enum Input {
+    /// A line, without its line ending.
+    Line(String),
+    /// The line was abandoned with Ctrl-C.
+    Interrupted,
+    /// The end of input, or Ctrl-D on an empty line.
+    Eof,
+}
+
+/// Suggests completions for the word at the cursor when Tab is pressed.
+pub trait Completer {
+    /// Given a line and the (byte) position of the cursor in it, returns
+    /// where the text to be completed starts, and what it could be replaced
+    /// with.
+    fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>);
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
+    // the text most recently removed by Ctrl-K, Ctrl-U or Ctrl-W
+    killed: Vec<char>,
+    completer: Option<Box<dyn Completer>>,
+}
+
+/// A keypress, decoded from the bytes a terminal sends for it.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    Up,
+    Down,
+    Unknown,
+}
+
+/// The line being edited, and the position of the cursor in it.
+struct Line {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor { history: vec!(), history_file: None, killed: vec!(), completer: None }
+    }
+
+    /// Uses `completer` to complete words when Tab is pressed.
+    pub fn set_completer<C: Completer + 'static>(&mut self, completer: C) {
+        self.completer = Some(Box::new(completer));
+    }
+
+    /// Loads history from the file at `path` (if it exists), and saves every
+    /// line added to the history from now on to it.
+    pub fn set_history_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        match File::open(&path) {
+            Ok(f) => {
+                let lines = BufReader::new(f).lines().collect::<Result<Vec<_>, _>>()?;
+                for line in &lines {
+                    self.push_history(line);
+                }
+                // The file is only ever appended to, so it is trimmed here.
+                if lines.len() > HISTORY_SIZE {
+                    let mut contents = self.history.join("\n");
+                    contents.push('\n');
+                    fs::write(&path, contents)?;
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        self.history_file = Some(path);
+        Ok(())
+    }
+
+    /// The lines in the history, oldest first.
+    #[cfg(test)]
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Adds a line to the history, unless it is blank or the same as the last
+    /// one, and saves it to the history file if there is one.
+    pub fn add_history(&mut self, line: &str) {
+        if !self.push_history(line) {
+            return;
+        }
+        if let Some(ref path) = self.history_file {
+            // Losing history isn't worth interrupting the user over.
+            let _ = OpenOptions::new().create(true).append(true).open(path)
+                                      .and_then(|mut f| writeln!(f, "{}", line));
+        }
+    }
+
+    fn push_history(&mut self, line: &str) -> bool {
+        if line.trim().is_empty() || self.history.last().map(|l| &l[..]) == Some(line) {
+            return false;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        true
+    }
+
+    /// Prints `prompt` and reads a line from stdin, letting the user edit it
+    /// if stdin is a terminal.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        #[cfg(unix)]
+        if is_terminal() {
+            if let Ok(_raw) = RawMode::enable() {
+                let stdin = stdin();
+                let mut input = stdin.lock();
+                return self.edit(prompt, &mut input, &mut stdout());
+            }
+        }
+
+        let mut out = stdout();
+        write!(out, "{}", prompt)?;
+        out.flush()?;
+
+        let mut line = String::new();
+        if stdin().read_line(&mut line)? == 0 {
+            return Ok(Input::Eof);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Input::Line(line))
+    }
+
+    /// Reads a line from `input`, which should be a terminal in raw mode,
+    /// showing the prompt and the line as it is edited on `out`. A line that
+    /// is entered is added to the history.
+    pub fn edit<R: Read, W: Write>(&mut self, prompt: &str, input: &mut R, out: &mut W) -> io::Result<Input> {
+        let mut line = Line { chars: vec!(), pos: 0 };
+        // Which history entry is shown, where `history.len()` is the line
+        // being typed, which is kept in `typed` while browsing the history.
+        let mut hist_pos = self.history.len();
+        let mut typed = vec!();
+
+        refresh(out, prompt, &line)?;
+        loop {
+            let mut key = match read_key(input)? {
+                None if line.chars.is_empty() => return Ok(Input::Eof),
+                None => Key::Enter,
+                Some(key) => key,
+            };
+
+            if key == Key::Ctrl('r') {
+                match self.search(input, out, &mut line)? {
+                    None => {
+                        refresh(out, prompt, &line)?;
+                        continue;
+                    },
+                    Some(k) => key = k,
+                }
+            }
+
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    let text: String = line.chars.into_iter().collect();
+                    self.add_history(&text);
+                    return Ok(Input::Line(text));
+                },
+                Key::Ctrl('c') => {
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Ok(Input::Interrupted);
+                },
+                Key::Ctrl('d') if line.chars.is_empty() => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Input::Eof);
+                },
+                Key::Char(c) => {
+                    line.chars.insert(line.pos, c);
+                    line.pos += 1;
+                },
+                Key::Backspace if line.pos > 0 => {
+                    line.pos -= 1;
+                    line.chars.remove(line.pos);
+                },
+                Key::Delete | Key::Ctrl('d') if line.pos < line.chars.len() => {
+                    line.chars.remove(line.pos);
+                },
+                Key::Left | Key::Ctrl('b') => line.pos = line.pos.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => line.pos = (line.pos + 1).min(line.chars.len()),
+                Key::Home | Key::Ctrl('a') => line.pos = 0,
+                Key::End | Key::Ctrl('e') => line.pos = line.chars.len(),
+                Key::WordLeft => line.pos = line.word_start(),
+                Key::WordRight => line.pos = line.word_end(),
+                Key::Ctrl('k') => {
+                    self.killed = line.chars.split_off(line.pos);
+                },
+                Key::Ctrl('u') => {
+                    self.killed = line.chars.drain(..line.pos).collect();
+                    line.pos = 0;
+                },
+                Key::Ctrl('w') => {
+                    let start = line.word_start();
+                    self.killed = line.chars.drain(start..line.pos).collect();
+                    line.pos = start;
+                },
+                Key::Ctrl('y') => {
+                    for &c in self.killed.iter().rev() {
+                        line.chars.insert(line.pos, c);
+                    }
+                    line.pos += self.killed.len();
+                },
+                Key::Up | Key::Ctrl('p') if hist_pos > 0 => {
+                    if hist_pos == self.history.len() {
+                        typed = line.chars.clone();
+                    }
+                    hist_pos -= 1;
+                    line.set(self.history[hist_pos].chars().collect());
+                },
+                Key::Down | Key::Ctrl('n') if hist_pos < self.history.len() => {
+                    hist_pos += 1;
+                    if hist_pos == self.history.len() {
+                        line.set(typed.clone());
+                    } else {
+                        line.set(self.history[hist_pos].chars().collect());
+                    }
+                },
+                Key::Tab => self.complete(out, &mut line)?,
+                Key::Ctrl('l') => write!(out, "\x1b[H\x1b[2J")?,
+                _ => {},
+            }
+            refresh(out, prompt, &line)?;
+        }
+    }
+
+    // Completes the text before the cursor as far as all of its completions
+    // agree, or lists them if that doesn't get any further.
+    fn complete<W: Write>(&self, out: &mut W, line: &mut Line) -> io::Result<()> {
+        let completer = match self.completer {
+            Some(ref completer) => completer,
+            None => return Ok(()),
+        };
+
+        let text: String = line.chars.iter().collect();
+        let pos = line.chars[..line.pos].iter().map(|c| c.len_utf8()).sum();
+        let (start, candidates) = completer.complete(&text, pos);
+        if candidates.is_empty() {
+            return write!(out, "\x07");
+        }
+
+        let start = text[..start].chars().count();
+        let prefix = common_prefix(&candidates);
+        if prefix.len() > line.pos - start {
+            line.chars.splice(start..line.pos, prefix.iter().cloned());
+            line.pos = start + prefix.len();
+        } else if candidates.len() > 1 {
+            write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+        }
+        Ok(())
+    }
+
+    // Searches backwards through the history for entries containing what is
+    // typed, until another key ends the search. Ctrl-G or Ctrl-C cancels the
+    // search, leaving `line` as it was, and returns `None`. Any other key
+    // puts the entry found into `line`, and is returned to be handled as
+    // usual.
+    fn search<R: Read, W: Write>(&mut self, input: &mut R, out: &mut W, line: &mut Line) -> io::Result<Option<Key>> {
+        let mut query = String::new();
+        // The entry last found, and whether the latest search found anything.
+        let mut found: Option<usize> = None;
+        let mut failed = false;
+
+        loop {
+            let shown = found.map(|i| &self.history[i][..]).unwrap_or("");
+            let label = if failed { "failed reverse-i-search" } else { "reverse-i-search" };
+            write!(out, "\r({})`{}': {}\x1b[K", label, query, shown)?;
+            out.flush()?;
+
+            let key = match read_key(input)? {
+                None => Key::Enter,
+                Some(key) => key,
+            };
+
+            // Where the next search starts, counting down from one past it.
+            let from = match key {
+                Key::Ctrl('g') | Key::Ctrl('c') => return Ok(None),
+                Key::Ctrl('r') => found.unwrap_or(self.history.len()),
+                Key::Char(c) => {
+                    query.push(c);
+                    found.map(|i| i + 1).unwrap_or(self.history.len())
+                },
+                Key::Backspace => {
+                    query.pop();
+                    self.history.len()
+                },
+                _ => {
+                    if let Some(i) = found {
+                        line.set(self.history[i].chars().collect());
+                    }
+                    return Ok(Some(key));
+                },
+            };
+
+            match self.history[..from].iter().rposition(|entry| entry.contains(&query[..])) {
+                Some(i) => {
+                    found = Some(i);
+                    failed = false;
+                },
+                None => failed = true,
+            }
+        }
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Editor {
+        Editor::new()
+    }
+}
+
+impl Line {
+    /// Replaces the text, moving the cursor to the end.
+    fn set(&mut self, chars: Vec<char>) {
+        self.pos = chars.len();
+        self.chars = chars;
+    }
+
+    /// Where the word before the cursor starts.
+    fn word_start(&self) -> usize {
+        let mut i = self.pos;
+        while i > 0 && !is_word_char(self.chars[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word_char(self.chars[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    /// Where the word after the cursor ends.
+    fn word_end(&self) -> usize {
+        let mut i = self.pos;
+        while i < self.chars.len() && !is_word_char(self.chars[i]) {
+            i += 1;
+        }
+        while i < self.chars.len() && is_word_char(self.chars[i]) {
+            i += 1;
+        }
+        i
+    }
+}
+
+/// The longest prefix all of `words` start with.
+fn common_prefix(words: &[String]) -> Vec<char> {
+    let mut prefix: Vec<char> = words[0].chars().collect();
+    for word in &words[1..] {
+        let len = prefix.iter().zip(word.chars()).take_while(|&(a, b)| *a == b).count();
+        prefix.truncate(len);
+    }
+    prefix
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !"()[]{}'\"".contains(c)
+}
+
+/// Redraws the prompt and line, and puts the cursor where it belongs.
+fn refresh<W: Write>(out: &mut W, prompt: &str, line: &Line) -> io::Result<()> {
+    let text: String = line.chars.iter().collect();
+    write!(out, "\r{}{}\x1b[K", prompt, text)?;
+    let after = line.chars.len() - line.pos;
+    if after > 0 {
+        write!(out, "\x1b[{}D", after)?;
+    }
+    out.flush()
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the next keypress, or `None` at the end of input.
+fn read_key<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+    let b = match read_byte(input)? {
+        None => return Ok(None),
+        Some(b) => b,
+    };
+
+    let key = match b {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        8 | 127 => Key::Backspace,
+        1..=26 => Key::Ctrl((b'a' + b - 1) as char),
+        27 => read_escape(input)?,
+        0..=127 => Key::Char(b as char),
+        _ => {
+            // the first byte of a multi-byte UTF-8 character says how many
+            // more there are
+            let len = if b >= 0xf0 { 4 } else if b >= 0xe0 { 3 } else { 2 };
+            let mut bytes = vec!(b);
+            for _ in 1..len {
+                match read_byte(input)? {
+                    None => break,
+                    Some(b) => bytes.push(b),
+                }
+            }
+            match String::from_utf8(bytes) {
+                Ok(s) => Key::Char(s.chars().next().unwrap()),
+                Err(_) => Key::Unknown,
+            }
+        },
+    };
+    Ok(Some(key))
+}
+
+// Decodes what follows an escape byte: the escape sequences for the arrow,
+// home, end and delete keys, or Alt-B and Alt-F.
+fn read_escape<R: Read>(input: &mut R) -> io::Result<Key> {
+    let kind = match read_byte(input)? {
+        Some(b'b') => return Ok(Key::WordLeft),
+        Some(b'f') => return Ok(Key::WordRight),
+        Some(b @ b'[') | Some(b @ b'O') => b,
+        _ => return Ok(Key::Unknown),
+    };
+
+    // Parameters (digits separated by semicolons) then a final byte.
+    let mut params = String::new();
+    let last = loop {
+        match read_byte(input)? {
+            Some(b) if b.is_ascii_digit() || b == b';' => params.push(b as char),
+            Some(b) => break b,
+            None => return Ok(Key::Unknown),
+        }
+    };
+
+    // Ctrl (5) or Alt (3) with an arrow key moves by words.
+    let modified = kind == b'[' && (params.ends_with(";5") || params.ends_with(";3"));
+    let key = match last {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' if modified => Key::WordRight,
+        b'D' if modified => Key::WordLeft,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'~' => match &params[..] {
+            "1" | "7" => Key::Home,
+            "3" => Key::Delete,
+            "4" | "8" => Key::End,
+            _ => Key::Unknown,
+        },
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+#[cfg(unix)]
+fn is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Puts the terminal into raw mode, where keypresses are read as they
+/// happen and aren't echoed, until it is dropped.
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        unsafe {
+            let mut termios: libc::termios = ::std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+
+            termios.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+            termios.c_cflag |= libc::CS8;
+            termios.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+
+            // Pending input is kept, so that pasting several lines works.
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawMode { original })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+        }
+    }
+}
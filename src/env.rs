@@ -0,0 +1,329 @@
+//! Environments map symbols to values.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use super::{Expr, Expression, Closure, HashMap};
+
+/// The environment an expression is evaluated in: a chain of frames, each of
+/// which points at the frame it was created from. Looking up a symbol walks
+/// the chain from the innermost frame outwards, ending at the root frame,
+/// which holds the top-level definitions.
+///
+/// Cloning an `Env` is cheap, and the clone shares its frames with the
+/// original, so a closure that captures an `Env` sees later changes to it
+/// (in particular, functions that are `defun`ed after the closure was made).
+///
+/// A frame that binds a closure made in it, or in a frame inside it, is kept
+/// alive by that closure, and the closure by the frame. Frames that are only
+/// kept alive this way are freed by `collect_cycles`, which runs every so
+/// often as frames are made.
+#[derive(Clone)]
+pub struct Env {
+    frame: Rc<Frame>,
+}
+
+struct Frame {
+    bindings: RefCell<HashMap<String, Value>>,
+    parent: Option<Env>,
+}
+
+// What a symbol is bound to in a frame. A closure bound in the frame it was
+// made in (by `letrec`, say, or an internal `define`) is kept without its
+// environment, which would be the frame itself, so that the frame is freed as
+// soon as it is no longer used, without waiting for `collect_cycles`. It gets
+// the frame back when it is looked up.
+enum Value {
+    Expr(Expr),
+    Closure { params: Vec<String>, body: Vec<Expr>, sym: Option<String> },
+    // a `letrec` binding whose value hasn't been evaluated yet
+    Uninitialized,
+}
+
+impl Env {
+    /// Creates an environment with a single, empty root frame.
+    pub fn new() -> Env {
+        Env::with_parent(None)
+    }
+
+    /// Creates a new, empty frame whose parent is this environment.
+    pub fn extend(&self) -> Env {
+        Env::with_parent(Some(self.clone()))
+    }
+
+    fn with_parent(parent: Option<Env>) -> Env {
+        let frame = Rc::new(Frame { bindings: RefCell::new(HashMap::new()), parent });
+        let made = FRAMES.with(|frames| {
+            let mut frames = frames.borrow_mut();
+            frames.push(Rc::downgrade(&frame));
+            frames.len()
+        });
+        if made >= THRESHOLD.with(Cell::get) {
+            collect_cycles();
+        }
+        Env { frame }
+    }
+
+    /// Looks up a symbol, starting at the innermost frame. A symbol whose
+    /// nearest binding hasn't been initialized has no value.
+    pub fn find_copy(&self, key: &str) -> Option<Expr> {
+        self.find(key, |env, val| env.get(val)).flatten()
+    }
+
+    /// Whether the nearest binding of a symbol is one made by
+    /// `insert_uninitialized` that hasn't been given a value yet.
+    pub fn is_uninitialized(&self, key: &str) -> bool {
+        self.find(key, |_, val| matches!(*val, Value::Uninitialized)) == Some(true)
+    }
+
+    // Applies `f` to the innermost frame that binds `key`, and the value it
+    // binds it to.
+    fn find<T, F: Fn(&Env, &Value) -> T>(&self, key: &str, f: F) -> Option<T> {
+        let mut env = self;
+        loop {
+            if let Some(val) = env.frame.bindings.borrow().get(key) {
+                return Some(f(env, val));
+            }
+            match env.frame.parent {
+                Some(ref parent) => env = parent,
+                None => return None,
+            }
+        }
+    }
+
+    /// Every symbol bound in this environment, in no particular order. A
+    /// symbol bound in more than one frame is only listed once.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec!();
+        let mut env = self;
+        loop {
+            for key in env.frame.bindings.borrow().keys() {
+                if !names.contains(key) {
+                    names.push(key.clone());
+                }
+            }
+            match env.frame.parent {
+                Some(ref parent) => env = parent,
+                None => return names,
+            }
+        }
+    }
+
+    /// Binds a symbol in the innermost frame.
+    pub fn insert(&self, key: String, val: Expr) {
+        let val = self.value(val);
+        self.frame.bindings.borrow_mut().insert(key, val);
+    }
+
+    /// Binds a symbol in the innermost frame without giving it a value yet.
+    /// Until it is given one with `insert` or `set`, looking it up finds
+    /// nothing, rather than a binding in an outer frame.
+    pub fn insert_uninitialized(&self, key: String) {
+        self.frame.bindings.borrow_mut().insert(key, Value::Uninitialized);
+    }
+
+    /// Rebinds a symbol in the innermost frame that binds it. Returns whether
+    /// any frame did.
+    pub fn set(&self, key: &str, val: Expr) -> bool {
+        let mut env = self;
+        loop {
+            if let Some(slot) = env.frame.bindings.borrow_mut().get_mut(key) {
+                *slot = env.value(val);
+                return true;
+            }
+            match env.frame.parent {
+                Some(ref parent) => env = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Removes every binding in the innermost frame.
+    pub fn clear(&self) {
+        self.frame.bindings.borrow_mut().clear();
+    }
+
+    /// Binds a symbol in the root frame.
+    pub fn insert_global(&self, key: String, val: Expr) {
+        self.root().insert(key, val);
+    }
+
+    /// Whether this and `other` are the same environment, rather than two
+    /// that happen to hold the same bindings.
+    pub(crate) fn same(&self, other: &Env) -> bool {
+        Rc::ptr_eq(&self.frame, &other.frame)
+    }
+
+    /// How to keep `val` in this frame.
+    fn value(&self, val: Expr) -> Value {
+        match val {
+            Expression::Closure(ref c) if c.env.same(self) => {
+                Value::Closure { params: c.params.clone(), body: c.body.clone(), sym: c.sym.clone() }
+            },
+            val => Value::Expr(val),
+        }
+    }
+
+    /// The value of a binding kept in this frame.
+    fn get(&self, val: &Value) -> Option<Expr> {
+        Some(match *val {
+            Value::Expr(ref expr) => expr.clone(),
+            Value::Closure { ref params, ref body, ref sym } => {
+                let closure = Closure { params: params.clone(), body: body.clone(), env: self.clone(), sym: sym.clone() };
+                Expression::Closure(Rc::new(closure))
+            },
+            Value::Uninitialized => return None,
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.frame)
+    }
+
+    fn root(&self) -> &Env {
+        let mut env = self;
+        while let Some(ref parent) = env.frame.parent {
+            env = parent;
+        }
+        env
+    }
+}
+
+thread_local! {
+    // every frame made on this thread that may still be alive, for
+    // `collect_cycles` to look through
+    static FRAMES: RefCell<Vec<Weak<Frame>>> = const { RefCell::new(Vec::new()) };
+    // how many frames there can be in `FRAMES` before the next collection
+    static THRESHOLD: Cell<usize> = const { Cell::new(MIN_THRESHOLD) };
+}
+
+// The fewest frames made between two collections. After a collection, the
+// next one waits until there are twice as many frames as survived it, so the
+// time spent collecting stays proportional to the number of frames made.
+const MIN_THRESHOLD: usize = 4096;
+
+/// Frees the frames made on this thread that are only kept alive by cycles:
+/// by closures bound in them, or in frames they bind closures of, that are
+/// themselves only reachable from those frames. Returns how many frames were
+/// freed.
+///
+/// This runs by itself every so often as frames are made, so there is
+/// usually no need to call it.
+pub fn collect_cycles() -> usize {
+    let frames: Vec<Rc<Frame>> = FRAMES.with(|frames| {
+        let mut frames = frames.borrow_mut();
+        frames.retain(|frame| frame.strong_count() > 0);
+        frames.iter().filter_map(Weak::upgrade).collect()
+    });
+
+    let garbage = Graph::of(&frames).map(|graph| graph.garbage()).unwrap_or_default();
+    for &i in &garbage {
+        let bindings = mem::take(&mut *frames[i].bindings.borrow_mut());
+        drop(bindings);
+    }
+
+    let survivors = frames.len() - garbage.len();
+    THRESHOLD.with(|threshold| threshold.set(cmp::max(MIN_THRESHOLD, 2 * survivors)));
+    drop(frames);
+    FRAMES.with(|frames| frames.borrow_mut().retain(|frame| frame.strong_count() > 0));
+    garbage.len()
+}
+
+// The references between frames, and the closures bound in them, as in a
+// trial deletion cycle collector. Every strong reference to a node that
+// doesn't come from another node is from something outside the graph, like
+// the stack of the evaluator or a value held by an embedding application,
+// and everything that can be reached from such a node is alive. The rest can
+// only be reached through cycles, and can be freed.
+//
+// Closures in the bodies of closures (which only get there when code is made
+// by splicing in values) and in syntax rules aren't followed, so anything
+// they refer to is treated as alive.
+struct Graph {
+    // for each node, how many references to it come from outside the graph
+    external: Vec<isize>,
+    // for each node, the nodes it refers to: frames first, then closures
+    edges: Vec<Vec<usize>>,
+    frames: usize,
+}
+
+impl Graph {
+    // The graph of `frames`, which are all the live frames, and the closures
+    // bound in them. Returns `None` if a frame is being changed, which can't
+    // happen while it is being collected.
+    fn of(frames: &[Rc<Frame>]) -> Option<Graph> {
+        let index: HashMap<*const Frame, usize> = frames.iter().enumerate().map(|(i, f)| (Rc::as_ptr(f), i)).collect();
+        let frame_node = |env: &Env| index.get(&Rc::as_ptr(&env.frame)).cloned();
+
+        let mut closures: Vec<Rc<Closure>> = vec!();
+        let mut closure_index: HashMap<*const Closure, usize> = HashMap::new();
+        let mut edges: Vec<Vec<usize>> = vec!();
+
+        for frame in frames {
+            let mut out: Vec<usize> = frame.parent.as_ref().and_then(&frame_node).into_iter().collect();
+            let bindings = frame.bindings.try_borrow().ok()?;
+            for val in bindings.values() {
+                if let Value::Expr(ref expr) = *val {
+                    each_closure(expr, |closure| {
+                        let n = *closure_index.entry(Rc::as_ptr(closure)).or_insert_with(|| {
+                            closures.push(closure.clone());
+                            frames.len() + closures.len() - 1
+                        });
+                        out.push(n);
+                    });
+                }
+            }
+            edges.push(out);
+        }
+        for closure in &closures {
+            edges.push(frame_node(&closure.env).into_iter().collect());
+        }
+
+        // the references we hold while collecting aren't counted
+        let mut external: Vec<isize> = frames.iter().map(|f| Rc::strong_count(f) as isize - 1)
+            .chain(closures.iter().map(|c| Rc::strong_count(c) as isize - 1))
+            .collect();
+        for &n in edges.iter().flatten() {
+            external[n] -= 1;
+        }
+        if external.iter().any(|&n| n < 0) {
+            return None;
+        }
+        Some(Graph { external, edges, frames: frames.len() })
+    }
+
+    // The indices of the frames that can't be reached from outside.
+    fn garbage(&self) -> Vec<usize> {
+        let mut alive = vec!(false; self.edges.len());
+        let mut pending: Vec<usize> = (0..self.edges.len()).filter(|&n| self.external[n] > 0).collect();
+        while let Some(n) = pending.pop() {
+            if !alive[n] {
+                alive[n] = true;
+                pending.extend(self.edges[n].iter().cloned().filter(|&m| !alive[m]));
+            }
+        }
+        (0..self.frames).filter(|&n| !alive[n]).collect()
+    }
+}
+
+// Calls `f` with each closure (or macro) in a value, including those in
+// lists, but not those in the bodies of closures.
+fn each_closure<F: FnMut(&Rc<Closure>)>(expr: &Expr, mut f: F) {
+    let mut pending = vec!(expr);
+    while let Some(expr) = pending.pop() {
+        match *expr {
+            Expression::Closure(ref c) | Expression::Macro(ref c) => f(c),
+            Expression::List(ref vec) => pending.extend(vec.iter()),
+            _ => {},
+        }
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
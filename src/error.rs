@@ -0,0 +1,148 @@
+//! Errors that can arise while reading or evaluating expressions.
+
+use std::error;
+use std::fmt;
+
+use super::{Expr, Atom};
+use syntax::{readable, alias_base};
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// A symbol was evaluated that has no binding.
+    UnboundSymbol(String),
+    /// A symbol was evaluated whose `letrec` binding doesn't have a value
+    /// yet, because the value is still being evaluated.
+    Uninitialized(String),
+    /// The empty list was evaluated as a procedure call.
+    EmptyCall,
+    /// A procedure or special form was called with the wrong number of
+    /// arguments. `expr` is the offending call.
+    ArityMismatch { name: String, expected: Arity, actual: usize, expr: Expr },
+    /// A builtin was given a value of the wrong kind. `expr` is the offending
+    /// value.
+    WrongType { builtin: String, expected: &'static str, expr: Expr },
+    /// A special form was used with arguments of the wrong shape. `expr` is
+    /// the offending form.
+    BadForm { form: String, reason: &'static str, expr: Expr },
+    /// The operator of a call evaluated to something that cannot be called.
+    NotAProcedure(Expr),
+    /// An integer was divided by zero. `expr` is the offending call.
+    DivisionByZero(Expr),
+    /// The reader could not make sense of the input.
+    Read { reason: String, line: usize, column: usize },
+    /// The input ended partway through an expression, such as inside a list
+    /// or a string literal. `line` and `column` are where that expression
+    /// starts. More input may complete it, which is what the REPL asks for.
+    Incomplete { reason: String, line: usize, column: usize },
+    /// A file could not be read.
+    Io { path: String, reason: String },
+    /// An expression could not be converted to the Rust type `to`.
+    Conversion { to: &'static str, expr: Expr },
+    /// Evaluation recursed more deeply than the interpreter allows, or than
+    /// the stack has room for. `limit` is the depth it reached, and `calls`
+    /// holds the names of the procedures that were being called, outermost
+    /// first.
+    RecursionLimit { limit: usize, calls: Vec<String> },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnboundSymbol(ref s) =>
+                write!(f, "Symbol not found: {}", alias_base(s)),
+            Error::Uninitialized(ref s) =>
+                write!(f, "Symbol used before it was initialized: {}", alias_base(s)),
+            Error::EmptyCall =>
+                write!(f, "No procedure to call in ()"),
+            Error::ArityMismatch { ref name, expected, actual, ref expr } =>
+                write!(f, "`{}` expects {}, but was called with {}: {}",
+                       alias_base(name), expected, actual, readable(expr)),
+            Error::WrongType { ref builtin, expected, ref expr } =>
+                write!(f, "`{}` expects {}, but got: {}", builtin, expected, readable(expr)),
+            Error::BadForm { ref form, reason, ref expr } =>
+                write!(f, "Invalid `{}`: {}: {}", form, reason, readable(expr)),
+            Error::NotAProcedure(ref expr) =>
+                write!(f, "Not a procedure: {}", readable(expr)),
+            Error::DivisionByZero(ref expr) =>
+                write!(f, "Division by zero: {}", readable(expr)),
+            Error::Read { ref reason, line, column }
+            | Error::Incomplete { ref reason, line, column } =>
+                write!(f, "{} at line {}, column {}", reason, line, column),
+            Error::Io { ref path, ref reason } =>
+                write!(f, "Couldn't read {}: {}", path, reason),
+            Error::Conversion { to, ref expr } =>
+                write!(f, "Can't convert to {}: {}", to, readable(expr)),
+            Error::RecursionLimit { limit, ref calls } => {
+                write!(f, "Maximum recursion depth ({}) exceeded", limit)?;
+                if !calls.is_empty() {
+                    write!(f, ", in: {}", show_calls(calls))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// How many arguments a procedure or special form takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exactly(m) => n == m,
+            Arity::AtLeast(m) => n >= m,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (prefix, n) = match *self {
+            Arity::Exactly(n) => ("", n),
+            Arity::AtLeast(n) => ("at least ", n),
+        };
+        write!(f, "{}{} argument{}", prefix, n, if n == 1 { "" } else { "s" })
+    }
+}
+
+impl Error {
+    /// The expression this error is about, if there is one. To find where
+    /// in the source text the error happened, use `Interpreter::eval_traced`
+    /// instead: the same value can appear in more than one place.
+    pub fn expr(&self) -> Option<Expr> {
+        match *self {
+            Error::UnboundSymbol(ref s) | Error::Uninitialized(ref s) => Some(Atom(s.clone())),
+            Error::ArityMismatch { ref expr, .. }
+            | Error::WrongType { ref expr, .. }
+            | Error::BadForm { ref expr, .. }
+            | Error::NotAProcedure(ref expr)
+            | Error::DivisionByZero(ref expr)
+            | Error::Conversion { ref expr, .. } => Some(expr.clone()),
+            Error::EmptyCall => Some(Expr::empty_list()),
+            Error::Read { .. } | Error::Incomplete { .. } | Error::Io { .. } | Error::RecursionLimit { .. } => None,
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Renders a call chain, collapsing runs of calls to the same procedure, as
+/// in "main -> f (x998) -> g".
+fn show_calls(calls: &[String]) -> String {
+    let mut runs: Vec<(&str, usize)> = vec!();
+    for name in calls.iter().map(|name| alias_base(name)) {
+        match runs.last_mut() {
+            Some(&mut (last, ref mut n)) if last == name => *n += 1,
+            _ => runs.push((name, 1)),
+        }
+    }
+
+    let shown: Vec<String> = runs.iter().map(|&(name, n)| {
+        if n == 1 { name.to_string() } else { format!("{} (x{})", name, n) }
+    }).collect();
+    shown.join(" -> ")
+}
@@ -1,179 +1,590 @@
-use super::{Expr, Env, HashMap, Expression, Nil, Atom, List};
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use super::{Expr, Env, Expression, Closure, Native, Nil, Atom, List, Num};
+use error::{Error, Arity};
+use interp::Interpreter;
+use syntax::{self, SyntaxRules};
+
+pub type EvalResult = Result<Expr, Error>;
+
+/// The symbols `eval` treats specially at the head of a list, and how each
+/// of those forms is written.
+pub const SPECIAL_FORMS: &[(&str, &str)] = &[
+    ("quote", "(quote expr)"),
+    ("quasiquote", "(quasiquote template), or `template"),
+    ("unquote", "(unquote expr), or ,expr, inside a quasiquote template"),
+    ("unquote-splicing", "(unquote-splicing expr), or ,@expr, inside a list in a quasiquote template"),
+    ("cond", "(cond (test expr ...) ...)"),
+    ("if", "(if test then else), or (if test then)"),
+    ("when", "(when test body ...)"),
+    ("unless", "(unless test body ...)"),
+    ("and", "(and expr ...)"),
+    ("or", "(or expr ...)"),
+    ("case", "(case key ((datums ...) expr ...) ... (else expr ...))"),
+    ("begin", "(begin expr ...)"),
+    ("define", "(define name value), or (define (name params ...) body ...)"),
+    ("set!", "(set! name value)"),
+    ("defun", "(defun name (params ...) body ...)"),
+    ("defmacro", "(defmacro name (params ...) body ...)"),
+    ("define-syntax", "(define-syntax name (syntax-rules (literals ...) (pattern template) ...))"),
+    ("syntax-rules", "(syntax-rules (literals ...) (pattern template) ...), inside define-syntax"),
+    ("let", "(let ((name value) ...) body ...), or (let loop ((name value) ...) body ...)"),
+    ("let*", "(let* ((name value) ...) body ...)"),
+    ("letrec", "(letrec ((name value) ...) body ...)"),
+    ("lambda", "(lambda (params ...) body ...)"),
+    ("label", "(label name (lambda (params ...) body ...))"),
+];
+
+/// What is left to do once a special form or procedure call has done its
+/// part. Expressions in tail position are handed back to `eval` instead of
+/// being evaluated recursively, so that tail calls don't grow the Rust stack.
+enum Tail<'a> {
+    /// Evaluation is finished, with this value.
+    Done(Expr),
+    /// The value is that of this expression, evaluated in this environment.
+    /// It is borrowed if it is part of the expression being evaluated.
+    Eval(Env, Cow<'a, Expr>),
+    /// Like `Eval`, where the expression is the body of the procedure with
+    /// this name.
+    Call(String, Env, Expr),
+}
+
+type TailResult<'a> = Result<Tail<'a>, Error>;
 
-pub type EvalResult = Result<Expr, &'static str>;
+impl<'a> Tail<'a> {
+    // The same, with the expression copied if it is borrowed, for when what
+    // it is borrowed from is about to go away.
+    fn into_owned(self) -> Tail<'static> {
+        match self {
+            Tail::Done(val) => Tail::Done(val),
+            Tail::Eval(env, expr) => Tail::Eval(env, Cow::Owned(expr.into_owned())),
+            Tail::Call(name, env, expr) => Tail::Call(name, env, expr),
+        }
+    }
+}
 
 /// The heart and soul of Radicle.
-pub fn eval(env: &mut Env, expr: Expr) -> EvalResult {
-    match expr {
-        Nil => Ok(Nil),
+pub fn eval(interp: &mut Interpreter, env: &Env, expr: Expr) -> EvalResult {
+    let base = interp.enter()?;
+    let res = eval_loop(interp, env, Cow::Owned(expr), base);
+    interp.leave(base);
+    res
+}
+
+/// Like `eval`, but evaluates `expr` where it is. Subexpressions are
+/// evaluated this way, so that when evaluation fails, the interpreter can
+/// tell which of them failed, rather than just what it looked like.
+pub(crate) fn eval_ref(interp: &mut Interpreter, env: &Env, expr: &Expr) -> EvalResult {
+    let base = interp.enter()?;
+    let res = eval_loop(interp, env, Cow::Borrowed(expr), base);
+    interp.leave(base);
+    res
+}
+
+fn eval_loop<'a>(interp: &mut Interpreter, env: &Env, expr: Cow<'a, Expr>, base: usize) -> EvalResult {
+    let mut env = env.clone();
+    let mut expr = expr;
+    // the last call in `expr` itself whose body is being evaluated, which is
+    // where errors in the body are reported
+    let mut call: Option<&'a Expr> = None;
+
+    loop {
+        let res = match expr {
+            Cow::Borrowed(e) => eval_step(interp, &env, e),
+            Cow::Owned(ref e) => eval_step(interp, &env, e).map(Tail::into_owned),
+        };
+        let tail = match res {
+            Ok(tail) => tail,
+            Err(err) => {
+                interp.trace(&expr);
+                if let Some(call) = call {
+                    interp.trace(call);
+                }
+                return Err(err);
+            },
+        };
+
+        match tail {
+            Tail::Done(val) => return Ok(val),
+            Tail::Eval(next_env, next_expr) => {
+                env = next_env;
+                expr = next_expr;
+            },
+            Tail::Call(name, next_env, next_expr) => {
+                interp.call(base, name);
+                if let Cow::Borrowed(e) = expr {
+                    call = Some(e);
+                }
+                env = next_env;
+                expr = Cow::Owned(next_expr);
+            },
+        }
+    }
+}
+
+/// Evaluates `expr` as far as its tail position.
+fn eval_step<'a>(interp: &mut Interpreter, env: &Env, expr: &'a Expr) -> TailResult<'a> {
+    match *expr {
+        Nil => Ok(Tail::Done(Nil)),
         Atom(ref s) => {
             match env.find_copy(s) {
-                None => Err("Symbol not found."),
-                Some(expr) => Ok(expr),
+                Some(val) => Ok(Tail::Done(val)),
+                None if env.is_uninitialized(s) => Err(Error::Uninitialized(s.clone())),
+                // A symbol introduced by a hygienic macro that the
+                // expansion didn't bind refers to a global binding.
+                None if syntax::is_alias(s) => {
+                    let name = syntax::alias_base(s);
+                    interp.env().find_copy(name).map(Tail::Done).ok_or_else(|| Error::UnboundSymbol(name.to_string()))
+                },
+                None => Err(Error::UnboundSymbol(s.clone())),
             }
         },
-        List(vec) => {
-            if vec.len() == 0 {
-                return Err("No procedure to call. TODO: a better error message?");
+        Expression::Str(_) | Num(_) | Expression::Closure(_) | Expression::Native(_)
+        | Expression::Macro(_) | Expression::Syntax(_) => Ok(Tail::Done(expr.clone())),
+        List(ref vec) => {
+            if vec.is_empty() {
+                Err(Error::EmptyCall)
+            } else if is_special(&vec[0]) {
+                eval_special(interp, env, vec)
+            } else {
+                eval_func_call(interp, env, vec)
             }
+        },
+    }
+}
 
-            if is_symbol("quote", &vec[0]) {
-                if vec.len() != 2 {
-                    Err("`quote` expects exactly one argument.")
-                } else {
-                    Ok(vec[1].clone())
+/// Whether `op`, the head of a list, makes the list a special form.
+fn is_special(op: &Expr) -> bool {
+    SPECIAL_FORMS.iter().any(|&(name, _)| is_symbol(name, op))
+}
+
+/// Evaluates a special form. This is kept out of `eval_loop`, which every
+/// procedure call recurses through, so that its stack frame stays small.
+fn eval_special<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr]) -> TailResult<'a> {
+    if is_symbol("quote", &vec[0]) {
+        if vec.len() != 2 {
+            Err(arity_error("quote", Arity::Exactly(1), vec))
+        } else {
+            Ok(Tail::Done(syntax::strip(&vec[1])))
+        }
+    } else if is_symbol("quasiquote", &vec[0]) {
+        if vec.len() != 2 {
+            Err(arity_error("quasiquote", Arity::Exactly(1), vec))
+        } else {
+            eval_quasiquote(interp, env, &vec[1], 1).map(Tail::Done)
+        }
+    } else if is_symbol("unquote", &vec[0]) || is_symbol("unquote-splicing", &vec[0]) {
+        let form = vec[0].get_ref_atom();
+        Err(form_error(form, "can only be used inside a quasiquote", List(vec.to_vec())))
+    } else if is_symbol("cond", &vec[0]) {
+        eval_cond(interp, env, vec)
+    } else if is_symbol("if", &vec[0]) {
+        eval_if(interp, env, vec)
+    } else if is_symbol("when", &vec[0]) {
+        eval_when(interp, env, vec, "when")
+    } else if is_symbol("unless", &vec[0]) {
+        eval_when(interp, env, vec, "unless")
+    } else if is_symbol("and", &vec[0]) {
+        eval_and_or(interp, env, vec, "and")
+    } else if is_symbol("or", &vec[0]) {
+        eval_and_or(interp, env, vec, "or")
+    } else if is_symbol("case", &vec[0]) {
+        eval_case(interp, env, vec)
+    } else if is_symbol("begin", &vec[0]) {
+        eval_body(interp, env.clone(), &vec[1..])
+    } else if is_symbol("define", &vec[0]) {
+        eval_define(interp, env, vec).map(Tail::Done)
+    } else if is_symbol("set!", &vec[0]) {
+        eval_set(interp, env, vec).map(Tail::Done)
+    } else if is_symbol("defun", &vec[0]) {
+        eval_defun(env, vec, "defun").map(Tail::Done)
+    } else if is_symbol("defmacro", &vec[0]) {
+        eval_defun(env, vec, "defmacro").map(Tail::Done)
+    } else if is_symbol("define-syntax", &vec[0]) {
+        eval_define_syntax(env, vec).map(Tail::Done)
+    } else if is_symbol("let", &vec[0]) {
+        eval_let(interp, env, vec, "let")
+    } else if is_symbol("let*", &vec[0]) {
+        eval_let(interp, env, vec, "let*")
+    } else if is_symbol("letrec", &vec[0]) {
+        eval_let(interp, env, vec, "letrec")
+    } else if is_symbol("lambda", &vec[0]) || is_symbol("label", &vec[0]) {
+        eval_func_literal(env, vec).map(Tail::Done)
+    } else {
+        eval_func_call(interp, env, vec)
+    }
+}
+
+/// Builds the value of a quasiquote template, evaluating what is unquoted in
+/// it. `depth` is the number of quasiquotes the template is inside, less the
+/// number of unquotes: only unquotes that bring it to zero are evaluated, and
+/// the rest are left in place, so that nested quasiquotes work.
+fn eval_quasiquote(interp: &mut Interpreter, env: &Env, template: &Expr, depth: usize) -> EvalResult {
+    let vec = match *template {
+        List(ref vec) if !vec.is_empty() => vec,
+        _ => return Ok(syntax::strip(template)),
+    };
+
+    if let Some(form) = quasi_form(vec) {
+        if vec.len() != 2 {
+            return Err(arity_error(form, Arity::Exactly(1), vec));
+        }
+        return match form {
+            "unquote" if depth == 1 => eval_ref(interp, env, &vec[1]),
+            "unquote-splicing" if depth == 1 => {
+                Err(form_error(form, "can only be used inside a list", template.clone()))
+            },
+            "quasiquote" => quasi_wrap(form, eval_quasiquote(interp, env, &vec[1], depth + 1)?),
+            _ => quasi_wrap(form, eval_quasiquote(interp, env, &vec[1], depth - 1)?),
+        };
+    }
+
+    let mut res = vec!();
+    for item in vec {
+        match *item {
+            List(ref inner) if depth == 1 && quasi_form(inner) == Some("unquote-splicing") => {
+                if inner.len() != 2 {
+                    return Err(arity_error("unquote-splicing", Arity::Exactly(1), inner));
                 }
-            } else if is_symbol("atom", &vec[0]) {
-                eval_atom(env, vec)
-            } else if is_symbol("eq", &vec[0]) {
-                eval_eq(env, vec)
-            } else if is_symbol("first", &vec[0]) {
-                eval_first(env, vec)
-            } else if is_symbol("rest", &vec[0]) {
-                eval_rest(env, vec)
-            } else if is_symbol("cons", &vec[0]) {
-                eval_cons(env, vec)
-            } else if is_symbol("cond", &vec[0]) {
-                eval_cond(env, vec)
-            } else if is_symbol("defun", &vec[0]) {
-                eval_defun(env, vec)
-            } else {
-                eval_func_call(env, vec)
-            }
+                match eval_ref(interp, env, &inner[1])? {
+                    List(vals) => res.extend(vals),
+                    _ => return Err(form_error("unquote-splicing", "the expression must evaluate to a list", item.clone())),
+                }
+            },
+            _ => res.push(eval_quasiquote(interp, env, item, depth)?),
         }
     }
+    Ok(List(res))
 }
 
-fn eval_atom(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
-    if vec.len() != 2 {
-        Err("`atom` expects exactly one argument.")
-    } else {
-        let val = try!( eval(env, vec[1].clone() ) );
-        if val.is_atom() || val.is_empty_list() {
-            Ok( Atom("t".to_string()) )
-        } else {
-            Ok( Expression::empty_list() )
+/// Which of `quasiquote`, `unquote` and `unquote-splicing` a list is a use
+/// of, if any.
+fn quasi_form(vec: &[Expr]) -> Option<&'static str> {
+    ["quasiquote", "unquote", "unquote-splicing"].iter().cloned()
+        .find(|form| !vec.is_empty() && is_symbol(form, &vec[0]))
+}
+
+fn quasi_wrap(form: &str, expr: Expr) -> EvalResult {
+    Ok( List(vec!(Atom(form.to_string()), expr)) )
+}
+
+fn eval_cond<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr]) -> TailResult<'a> {
+    for clause in &vec[1..] {
+        match *clause {
+            List(ref list) => {
+                if list.len() < 2 {
+                    return Err(form_error("cond", "clauses must have a test and at least one expression", clause.clone()));
+                } else {
+                    let val = eval_ref(interp, env, &list[0])?;
+
+                    if is_true(&val) {
+                        return eval_body(interp, env.clone(), &list[1..]);
+                    }
+                }
+            },
+            _ => return Err(form_error("cond", "clauses must be lists", clause.clone())),
         }
     }
+
+    Ok(Tail::Done(Nil))
 }
 
+/// Whether a value counts as true for `cond` and the other conditionals: only
+/// `t` does.
+fn is_true(val: &Expr) -> bool {
+    matches!(*val, Atom(ref s) if s == "t")
+}
 
-fn eval_eq(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
+fn eval_if<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr]) -> TailResult<'a> {
+    if vec.len() != 3 && vec.len() != 4 {
+        return Err(form_error("if", "expected (if test then else) or (if test then)", List(vec.to_vec())));
+    }
 
-    if vec.len() != 3 {
-        Err("`eq` expects exactly two arguments.")
+    let test = eval_ref(interp, env, &vec[1])?;
+    match vec.get(if is_true(&test) { 2 } else { 3 }) {
+        Some(branch) => Ok(Tail::Eval(env.clone(), Cow::Borrowed(branch))),
+        None => Ok(Tail::Done(Nil)),
+    }
+}
+
+/// Evaluates a `when`, whose body is evaluated if the test is true, or an
+/// `unless`, whose body is evaluated if it isn't.
+fn eval_when<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr], form: &str) -> TailResult<'a> {
+    if vec.len() < 3 {
+        return Err(arity_error(form, Arity::AtLeast(2), vec));
+    }
+
+    let test = eval_ref(interp, env, &vec[1])?;
+    if is_true(&test) == (form == "when") {
+        eval_body(interp, env.clone(), &vec[2..])
     } else {
-        let val1 = try!( eval(env, vec[1].clone()) );
-        let val2 = try!( eval(env, vec[2].clone() ) );
-        if (val1.is_empty_list() && val2.is_empty_list())
-           || (val1.is_atom() && val2.is_atom() && val1.eq(&val2)) {
-            Ok( Atom("t".to_string()) )
-        } else {
-            Ok( Expression::empty_list() )
+        Ok(Tail::Done(Nil))
+    }
+}
+
+/// Evaluates an `and` or an `or`. Their arguments are evaluated in order
+/// until one of them decides the result: for `and`, the first that is not
+/// true, which makes the result `()`, and for `or`, the first that is,
+/// which makes it `t`. The last argument is in tail position, and gives the
+/// result if none before it decided it.
+fn eval_and_or<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr], form: &str) -> TailResult<'a> {
+    let decider = form == "or";
+    let result = |decided: bool| if decided { Atom("t".to_string()) } else { Expression::empty_list() };
+
+    let (last, exprs) = match vec[1..].split_last() {
+        None => return Ok(Tail::Done(result(!decider))),
+        Some(split) => split,
+    };
+    for expr in exprs {
+        if is_true(&eval_ref(interp, env, expr)?) == decider {
+            return Ok(Tail::Done(result(decider)));
         }
     }
+    Ok(Tail::Eval(env.clone(), Cow::Borrowed(last)))
 }
 
+/// Evaluates a `case`: the key is compared with the (unevaluated) datums of
+/// each clause in turn, and the body of the first clause with an equal
+/// datum, or the `else` clause, which must come last, is evaluated.
+fn eval_case<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr]) -> TailResult<'a> {
+    if vec.len() < 2 {
+        return Err(arity_error("case", Arity::AtLeast(1), vec));
+    }
 
-fn eval_first(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
+    let is_else = |clause: &Expr| match *clause {
+        List(ref list) => !list.is_empty() && is_symbol("else", &list[0]),
+        _ => false,
+    };
+    if let Some(clause) = vec[2..].iter().rev().skip(1).find(|&c| is_else(c)) {
+        return Err(form_error("case", "else can only be the last clause", clause.clone()));
+    }
 
-    if vec.len() != 2 {
-        Err("`first` expects exactly one argument.")
-    } else {
-        let val = try!( eval(env, vec[1].clone() ) );
-        if val.is_list() && !val.is_empty_list() {
-            let list = val.unwrap_list();
-            Ok( list[0].clone() )
-        } else {
-            Err("`first`'s argument must be a non-empty list")
+    let key = eval_ref(interp, env, &vec[1])?;
+    for clause in &vec[2..] {
+        let list = match *clause {
+            List(ref list) if list.len() >= 2 => list,
+            _ => return Err(form_error("case", "clauses must have datums and at least one expression", clause.clone())),
+        };
+        let matches = match list[0] {
+            List(ref datums) => datums.iter().any(|d| syntax::strip(d) == key),
+            ref head if is_symbol("else", head) => true,
+            _ => return Err(form_error("case", "the datums of a clause must be a list, or else", clause.clone())),
+        };
+        if matches {
+            return eval_body(interp, env.clone(), &list[1..]);
         }
     }
+
+    Ok(Tail::Done(Nil))
 }
 
-fn eval_rest(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
+/// Evaluates the expressions of a body, such as that of a `begin`, in order
+/// in `env`. The last one is in tail position, and its value is the value of
+/// the body.
+fn eval_body<'a>(interp: &mut Interpreter, env: Env, body: &'a [Expr]) -> TailResult<'a> {
+    let (last, exprs) = match body.split_last() {
+        None => return Ok(Tail::Done(Nil)),
+        Some(split) => split,
+    };
+    for expr in exprs {
+        eval_ref(interp, &env, expr)?;
+    }
+    Ok(Tail::Eval(env, Cow::Borrowed(last)))
+}
 
-    if vec.len() != 2 {
-        Err("`rest` expects exactly one argument.")
+/// A single expression that evaluates the expressions of a body in order:
+/// the expression itself if there is only one, and a `begin` of them if not.
+fn sequence(body: &[Expr]) -> Expr {
+    if body.len() == 1 {
+        body[0].clone()
     } else {
-        let val = try!( eval(env, vec[1].clone() ) );
-        if val.is_list() && !val.is_empty_list() {
-            let mut list = val.unwrap_list();
-            list.remove(0);
-            Ok( List(list) )
-        } else {
-            Err("`rest`'s argument must be a non-empty list")
-        }
+        let mut vec = vec!(Atom("begin".to_string()));
+        vec.extend(body.iter().cloned());
+        List(vec)
     }
 }
 
-fn eval_cons(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
+/// Evaluates a `define`, which binds a name in the innermost frame of `env`:
+/// globally at the top level, and locally inside a body. `(define (name
+/// params ...) body)` is short for defining `name` as a procedure.
+fn eval_define(interp: &mut Interpreter, env: &Env, vec: &[Expr]) -> EvalResult {
+    if vec.len() < 3 {
+        return Err(arity_error("define", Arity::AtLeast(2), vec));
+    }
+
+    let (name, val) = match vec[1] {
+        Atom(ref name) if vec.len() == 3 => (name.clone(), eval_ref(interp, env, &vec[2])?),
+        Atom(_) => return Err(arity_error("define", Arity::Exactly(2), vec)),
+        List(ref sig) if !sig.is_empty() && sig.iter().all(|p| p.is_atom()) => {
+            let params: Vec<&str> = sig[1..].iter().map(|p| p.get_ref_atom().as_str()).collect();
+            if let Some(reason) = bad_params(&params) {
+                return Err(form_error("define", reason, List(vec.to_vec())));
+            }
+            let label = vec!(Atom("label".to_string()),
+                             sig[0].clone(),
+                             lambda_expr(List(sig[1..].to_vec()), &vec[2..]));
+            (sig[0].get_ref_atom().clone(), eval_func_literal(env, &label)?)
+        },
+        _ => return Err(form_error("define", "expected (define name value) or (define (name params ...) body)", List(vec.to_vec()))),
+    };
+    env.insert(name, val);
+    Ok(Nil)
+}
 
+/// Evaluates a `set!`, which changes the value of the nearest binding of a
+/// name, and returns the new value.
+fn eval_set(interp: &mut Interpreter, env: &Env, vec: &[Expr]) -> EvalResult {
     if vec.len() != 3 {
-        Err("`cons` expects exactly two arguments.")
+        return Err(arity_error("set!", Arity::Exactly(2), vec));
+    }
+    if !vec[1].is_atom() {
+        return Err(form_error("set!", "the name must be a symbol", List(vec.to_vec())));
+    }
+
+    let name = vec[1].get_ref_atom();
+    let val = eval_ref(interp, env, &vec[2])?;
+    if env.set(name, val.clone()) {
+        return Ok(val);
+    }
+    // as when looking it up, an alias that isn't bound refers to a global
+    let base = syntax::alias_base(name);
+    if syntax::is_alias(name) && interp.env().set(base, val.clone()) {
+        Ok(val)
     } else {
-        let val1 = try!( eval(env, vec[1].clone()) );
-        let val2 = try!( eval(env, vec[2].clone()) );
+        Err(Error::UnboundSymbol(base.to_string()))
+    }
+}
 
-        match val2 {
-            List(mut list) => {
-                list.insert(0, val1);
-                Ok( List(list) )
-            },
-            _ => Err("`cons`'s second argument must be a list")
+/// Evaluates a `let`, `let*` or `letrec`. They differ in where the values of
+/// the bindings are evaluated: `let` evaluates them all in the enclosing
+/// environment, `let*` evaluates each one where the bindings before it are
+/// visible, and `letrec` evaluates them where all of the bindings are, so
+/// that local functions can call each other. Using a `letrec` binding before
+/// its value has been evaluated is an error.
+fn eval_let<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr], form: &str) -> TailResult<'a> {
+    if form == "let" && vec.len() >= 4 && vec[1].is_atom() {
+        return eval_named_let(interp, env, vec);
+    }
+    if vec.len() < 3 {
+        return Err(arity_error(form, Arity::AtLeast(2), vec));
+    }
+
+    let bindings = parse_bindings(form, &vec[1], vec)?;
+    if form != "let*" {
+        let names: Vec<&str> = bindings.iter().map(|binding| binding.0.as_str()).collect();
+        if let Some(reason) = bad_params(&names) {
+            return Err(form_error(form, reason, List(vec.to_vec())));
         }
     }
+    let new_env = match form {
+        "let" => {
+            let new_env = env.extend();
+            for (name, init) in bindings {
+                let val = eval_ref(interp, env, init)?;
+                new_env.insert(name, val);
+            }
+            new_env
+        },
+        "let*" => {
+            // Each binding gets a frame of its own, so that a closure made
+            // by one of the values doesn't see the bindings after it.
+            let mut new_env = env.extend();
+            for (name, init) in bindings {
+                let val = eval_ref(interp, &new_env, init)?;
+                new_env = new_env.extend();
+                new_env.insert(name, val);
+            }
+            new_env
+        },
+        _ => {
+            let new_env = env.extend();
+            for (name, _) in &bindings {
+                new_env.insert_uninitialized(name.clone());
+            }
+            for (name, init) in bindings {
+                let val = eval_ref(interp, &new_env, init)?;
+                new_env.insert(name, val);
+            }
+            new_env
+        },
+    };
+    eval_body(interp, new_env, &vec[2..])
 }
 
-fn eval_cond(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
-    for expr in vec.into_iter().skip(1) {
-        match expr {
-            List(list) => {
-                if list.len() != 2 {
-                    return Err("Invalid argument to `cond`");
-                } else {
-                    let res = eval(env, list[0].clone());
-                    let val = try!(res);
+/// Evaluates a named `let`, `(let loop ((name value) ...) body ...)`, which calls
+/// a local function `loop` with the given parameters and body, and the values
+/// as arguments. The body can call `loop` again to iterate.
+fn eval_named_let<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr]) -> TailResult<'a> {
+    let bindings = parse_bindings("let", &vec[2], vec)?;
+    let names: Vec<&str> = bindings.iter().map(|binding| binding.0.as_str()).collect();
+    if let Some(reason) = bad_params(&names) {
+        return Err(form_error("let", reason, List(vec.to_vec())));
+    }
+    let (params, inits): (Vec<String>, Vec<&Expr>) = bindings.into_iter().unzip();
 
-                    if val.eq( &Atom("t".to_string()) ) {
-                        return eval(env, list[1].clone() );
-                    }
-                }
-            },
-            _ => return Err("Invalid argument to `cond`"),
-        }
+    let sym = Some(vec[1].get_ref_atom().clone());
+    let closure = Rc::new(Closure { params, body: vec[3..].to_vec(), env: env.clone(), sym });
+    apply_closure(interp, env, closure, inits.into_iter())
+}
+
+/// The `(name value)` pairs of `bindings`, the bindings of `vec`, a use of
+/// `form`.
+fn parse_bindings<'a>(form: &str, bindings: &'a Expr, vec: &[Expr]) -> Result<Vec<(String, &'a Expr)>, Error> {
+    let pair = |binding: &'a Expr| match *binding {
+        List(ref pair) if pair.len() == 2 && pair[0].is_atom() => {
+            Some((pair[0].get_ref_atom().clone(), &pair[1]))
+        },
+        _ => None,
+    };
+    match *bindings {
+        List(ref bindings) => bindings.iter().map(pair).collect::<Option<_>>(),
+        _ => None,
+    }.ok_or_else(|| form_error(form, "the bindings must be a list of (name value) pairs", List(vec.to_vec())))
+}
+
+fn eval_define_syntax(env: &Env, vec: &[Expr]) -> EvalResult {
+    if vec.len() != 3 {
+        return Err(arity_error("define-syntax", Arity::Exactly(2), vec));
+    }
+    if !vec[1].is_atom() {
+        return Err(form_error("define-syntax", "the name must be a symbol", List(vec.to_vec())));
     }
 
+    let name = syntax::alias_base(vec[1].get_ref_atom()).to_string();
+    let rules = SyntaxRules::parse(&name, &vec[2])?;
+    env.insert_global(name, Expression::Syntax(Rc::new(rules)));
     Ok(Nil)
 }
 
-
-fn eval_defun(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
-    if vec.len() != 4 {
-        Err("`defun` expects exactly three arguments.")
+/// Evaluates a `defun`, or a `defmacro` (which is written the same way, but
+/// binds the name to a macro instead of a procedure).
+fn eval_defun(env: &Env, vec: &[Expr], form: &str) -> EvalResult {
+    if vec.len() < 4 {
+        Err(arity_error(form, Arity::AtLeast(3), vec))
     } else {
 
         if !vec[1].is_atom() {
-            return Err("First argument to `defun` must be a symbol");
+            return Err(form_error(form, "the name must be a symbol", List(vec.to_vec())));
         }
 
-        {
-            let params = vec[2].get_ref_list();
-            for p in params.iter() {
-                if !p.is_atom() {
-                    return Err("Second argument to `defun` must be a list of params");
-                } 
-            }
+        if !vec[2].is_list() || vec[2].get_ref_list().iter().any(|p| !p.is_atom()) {
+            return Err(form_error(form, "the parameters must be a list of symbols", List(vec.to_vec())));
         }
 
-        let func_name = vec[1].clone();
-        let params = vec[2].clone();
-        let body = vec[3].clone();
+        let params: Vec<&str> = vec[2].get_ref_list().iter().map(|p| p.get_ref_atom().as_str()).collect();
+        if let Some(reason) = bad_params(&params) {
+            return Err(form_error(form, reason, List(vec.to_vec())));
+        }
 
-        let label_expr = List( vec!(Atom("label".to_string()), 
-                                    func_name,
-                                    List( vec!(Atom("lambda".to_string()), params, body) ))
-                             );
-        env.bindings.insert(vec[1].clone().unwrap_atom(), label_expr);
+        let label = vec!(Atom("label".to_string()),
+                         vec[1].clone(),
+                         lambda_expr(vec[2].clone(), &vec[3..]));
+        let closure = match eval_func_literal(env, &label)? {
+            Expression::Closure(c) if form == "defmacro" => Expression::Macro(c),
+            closure => closure,
+        };
+        env.insert_global(vec[1].get_ref_atom().clone(), closure);
         Ok(Nil)
     }
 }
@@ -181,27 +592,28 @@ fn eval_defun(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
 
 struct FuncLiteral {
     params: Vec<String>,
-    body: Expr,
+    body: Vec<Expr>,
     sym: Option<String>, // lambdas will have None, labels will have Some
 }
 
 
-fn parse_func_literal(expr: &Expr) -> Option<FuncLiteral> {
-    match parse_lambda_literal(expr) {
-        None => parse_label_literal(expr),
-        lambda@Some(_) => lambda
-    }
+/// The expression `(lambda params body ...)`.
+fn lambda_expr(params: Expr, body: &[Expr]) -> Expr {
+    let mut vec = vec!(Atom("lambda".to_string()), params);
+    vec.extend(body.iter().cloned());
+    List(vec)
 }
 
-fn parse_lambda_literal(expr: &Expr) -> Option<FuncLiteral> {
-    if !expr.is_list() {
-        return None;
+fn parse_func_literal(vec: &[Expr]) -> Option<FuncLiteral> {
+    match parse_lambda_literal(vec) {
+        None => parse_label_literal(vec),
+        lambda@Some(_) => lambda
     }
+}
 
-    let vec = expr.get_ref_list();
-
-    if vec.len() != 3 
-       || !vec[1].is_list() 
+fn parse_lambda_literal(vec: &[Expr]) -> Option<FuncLiteral> {
+    if vec.len() < 3
+       || !vec[1].is_list()
        || !is_symbol("lambda", &vec[0]) {
         return None;
     }
@@ -217,23 +629,18 @@ fn parse_lambda_literal(expr: &Expr) -> Option<FuncLiteral> {
         }
     }
 
-    Some( FuncLiteral{ params: plist, body: vec[2].clone(), sym: None } )
+    Some( FuncLiteral{ params: plist, body: vec[2..].to_vec(), sym: None } )
 }
 
-fn parse_label_literal(expr: &Expr) -> Option<FuncLiteral> {
-    if !expr.is_list() {
-        return None;
-    }
-
-    let vec = expr.get_ref_list();
-
-    if vec.len() != 3 
-       || !vec[1].is_atom() 
+fn parse_label_literal(vec: &[Expr]) -> Option<FuncLiteral> {
+    if vec.len() != 3
+       || !vec[1].is_atom()
+       || !vec[2].is_list()
        || !is_symbol("label", &vec[0]) {
         return None;
     }
 
-    let lit = parse_lambda_literal(&vec[2]);
+    let lit = parse_lambda_literal(vec[2].get_ref_list());
 
     match lit {
         None => None,
@@ -244,74 +651,180 @@ fn parse_label_literal(expr: &Expr) -> Option<FuncLiteral> {
     }
 }
 
+/// What is wrong with a list of parameter names, if anything. `.` isn't
+/// allowed, as rest parameters aren't supported, and neither is binding the
+/// same name twice.
+fn bad_params<S: AsRef<str>>(params: &[S]) -> Option<&'static str> {
+    for (i, p) in params.iter().enumerate() {
+        if p.as_ref() == "." {
+            return Some("rest parameters (`.`) aren't supported");
+        }
+        if params[..i].iter().any(|q| q.as_ref() == p.as_ref()) {
+            return Some("a parameter can only appear once");
+        }
+    }
+    None
+}
+
+/// Whether `expr` is the symbol `op`, or an alias of it made by a hygienic
+/// macro.
 fn is_symbol(op: &str, expr: &Expr) -> bool {
     if expr.is_atom() {
         let expr_op = expr.get_ref_atom();
-        op == expr_op
+        op == syntax::alias_base(expr_op)
     } else {
         false
     }
 }
 
-fn eval_func_call(env: &mut Env, vec: Vec<Expr>) -> EvalResult {
-    let num_args = vec.len() - 1;
-
-    let mut vec_iter = vec.into_iter();
-    let mut op_expr = vec_iter.next().unwrap();
-
-    // There are two kinds of function calls: lambda calls and label
-    // calls. Labels are just lambdas that can cal themselves.
-    //
-    // We need to distinguish between literal function calls, which
-    // take the form:
-    //     ((lambda params body) expr1 ... exprn)
-    // or 
-    //     (label sym ((lambda params body)) expr1 ... exprn)
-    //
-    // and non-literals, which take the form (expr0 expr1 ... exprn),
-    // where expr1 is an expression that evaluates to a function
-    // literal. The reason we need to make this distinction is that
-    // label and lambda expressions do not evaluate to anything, so 
-    // if the operator expression is such a literal we must not eval
-    // it. However, if it is not such a literal, we need to eval it
-    // to see whether it evaluates to a function literal.
-
-    let func_lit = match parse_func_literal(&op_expr) {
-        Some(f) => f,
+/// Evaluates a `lambda` or `label` expression into a closure that captures
+/// the environment it is evaluated in.
+fn eval_func_literal(env: &Env, vec: &[Expr]) -> EvalResult {
+    match parse_func_literal(vec) {
         None => {
-            op_expr = try!( eval(env, op_expr) );
-            match parse_func_literal(&op_expr) {
-                None => return Err("Unrecognized expression."),
-                Some(f) => f,
+            let form = if is_symbol("label", &vec[0]) { "label" } else { "lambda" };
+            Err(form_error(form, "expected (lambda (params...) body...) or (label name (lambda ...))", List(vec.to_vec())))
+        },
+        Some(FuncLiteral{params, body, sym}) => {
+            if let Some(reason) = bad_params(&params) {
+                let form = if sym.is_some() { "label" } else { "lambda" };
+                return Err(form_error(form, reason, List(vec.to_vec())));
             }
+            Ok( Expression::Closure(Rc::new(Closure{ params, body, env: env.clone(), sym })) )
+        }
+    }
+}
+
+fn eval_func_call<'a>(interp: &mut Interpreter, env: &Env, vec: &'a [Expr]) -> TailResult<'a> {
+    // The operator should evaluate to a procedure. A quoted function literal,
+    // e.g. '(lambda (x) x), is a list rather than a procedure: it was never
+    // closed over the environment it was written in.
+    let op_val = eval_ref(interp, env, &vec[0])?;
+    match op_val {
+        Expression::Closure(c) => call_closure(interp, env, c, vec),
+        Expression::Native(native) => call_native(interp, env, &native, vec),
+        Expression::Macro(mac) => {
+            let expansion = expand_macro(interp, &mac, vec)?;
+            Ok(Tail::Eval(env.clone(), Cow::Owned(expansion)))
         },
-    };
+        Expression::Syntax(rules) => {
+            let expansion = rules.expand(interp, &List(vec.to_vec()))?;
+            Ok(Tail::Eval(env.clone(), Cow::Owned(expansion)))
+        },
+        List(ref list) if parse_func_literal(list).is_some() => {
+            let form = if is_symbol("label", &list[0]) { "label" } else { "lambda" };
+            Err(form_error(form, "a quoted function literal is not a procedure, remove the quote", op_val.clone()))
+        },
+        _ => Err(Error::NotAProcedure(op_val)),
+    }
+}
 
-    let FuncLiteral{params, body, sym} = func_lit;
-    let mut bindings = HashMap::<String, Expr>::new();
+fn call_native<'a>(interp: &mut Interpreter, env: &Env, native: &Native, vec: &[Expr]) -> TailResult<'a> {
+    let num_args = vec.len() - 1;
+    if !native.arity.accepts(num_args) {
+        return Err(Error::ArityMismatch {
+            name: native.name.clone(),
+            expected: native.arity,
+            actual: num_args,
+            expr: List(vec.to_vec()),
+        });
+    }
 
-    match sym {
-        Some(s) => { bindings.insert(s, op_expr.clone()); },
-        None => {},
+    let mut args = Vec::with_capacity(num_args);
+    for arg in &vec[1..] {
+        args.push(eval_ref(interp, env, arg)?);
     }
+    Ok(Tail::Done((native.func)(interp, &args)?))
+}
 
-    if params.len() != num_args {
-        return Err("mismatch between number of procedure args and number of args called with.");
+fn call_closure<'a>(interp: &mut Interpreter, env: &Env, closure: Rc<Closure>, vec: &[Expr]) -> TailResult<'a> {
+    let num_args = vec.len() - 1;
+    if closure.params.len() != num_args {
+        return Err(Error::ArityMismatch {
+            name: closure.sym.clone().unwrap_or_else(|| "lambda".to_string()),
+            expected: Arity::Exactly(closure.params.len()),
+            actual: num_args,
+            expr: List(vec.to_vec()),
+        });
     }
+    apply_closure(interp, env, closure, vec[1..].iter())
+}
+
+/// Calls `closure` with the values of `args`, which must be as many as it has
+/// parameters.
+fn apply_closure<'a, 'b, I>(interp: &mut Interpreter, env: &Env, closure: Rc<Closure>, args: I) -> TailResult<'a>
+    where I: Iterator<Item = &'b Expr>
+{
+    let name = closure.sym.clone().unwrap_or_else(|| "lambda".to_string());
 
-    let mut param_iter = params.into_iter();
+    // Arguments are evaluated in the caller's environment, but the body is
+    // evaluated in the environment the closure was defined in.
+    let new_env = closure.env.extend();
 
-    for arg in vec_iter {
-        let next_param: String  = param_iter.next().unwrap();
-        bindings.insert(next_param, 
-                        try!( eval(env, arg) ));
+    if let Some(ref s) = closure.sym {
+        new_env.insert(s.clone(), Expression::Closure(closure.clone()));
     }
 
-    let mut new_env = env.clone();
-    for (k, v) in bindings.into_iter() {
-        new_env.bindings.insert(k, v);
+    for (param, arg) in closure.params.iter().zip(args) {
+        let val = eval_ref(interp, env, arg)?;
+        new_env.insert(param.clone(), val);
     }
 
-    let val = try!( eval(&mut new_env, body) );
-    Ok(val)
+    Ok(Tail::Call(name, new_env, sequence(&closure.body)))
+}
+
+/// Calls a macro's expander with the (unevaluated) arguments of `vec`, a use
+/// of the macro, returning the code to evaluate in its place.
+fn expand_macro(interp: &mut Interpreter, mac: &Closure, vec: &[Expr]) -> EvalResult {
+    let num_args = vec.len() - 1;
+    if mac.params.len() != num_args {
+        return Err(Error::ArityMismatch {
+            name: mac.sym.clone().unwrap_or_else(|| "macro".to_string()),
+            expected: Arity::Exactly(mac.params.len()),
+            actual: num_args,
+            expr: List(vec.to_vec()),
+        });
+    }
+
+    let new_env = mac.env.extend();
+    for (param, arg) in mac.params.iter().zip(&vec[1..]) {
+        new_env.insert(param.clone(), arg.clone());
+    }
+    eval(interp, &new_env, sequence(&mac.body))
+}
+
+/// If `form` is a use of a macro bound in `env`, expands it once and returns
+/// the expansion. Otherwise, returns `None`.
+pub fn macroexpand_1(interp: &mut Interpreter, env: &Env, form: &Expr) -> Result<Option<Expr>, Error> {
+    let op = match *form {
+        List(ref vec) if !vec.is_empty() && vec[0].is_atom() => {
+            let name = vec[0].get_ref_atom();
+            env.find_copy(name).or_else(|| interp.env().find_copy(syntax::alias_base(name)))
+        },
+        _ => None,
+    };
+    match op {
+        Some(Expression::Macro(mac)) => expand_macro(interp, &mac, form.get_ref_list()).map(Some),
+        Some(Expression::Syntax(rules)) => rules.expand(interp, form).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Expands `form` until it is no longer a use of a macro. Its subexpressions
+/// are left as they are.
+pub fn macroexpand(interp: &mut Interpreter, env: &Env, form: &Expr) -> EvalResult {
+    let mut form = form.clone();
+    while let Some(expansion) = macroexpand_1(interp, env, &form)? {
+        form = expansion;
+    }
+    Ok(form)
+}
+
+fn arity_error(name: &str, expected: Arity, vec: &[Expr]) -> Error {
+    let actual = vec.len() - 1;
+    Error::ArityMismatch { name: name.to_string(), expected, actual, expr: List(vec.to_vec()) }
+}
+
+fn form_error(form: &str, reason: &'static str, expr: Expr) -> Error {
+    Error::BadForm { form: form.to_string(), reason, expr }
 }
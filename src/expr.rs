@@ -1,10 +1,105 @@
 use std::fmt;
+use std::ptr;
+
+use super::Env;
+use error::Arity;
+use eval::EvalResult;
+use interp::Interpreter;
+use num::Number;
+use syntax::{self, SyntaxRules};
 
 #[derive(PartialEq, Clone)]
 pub enum Expression<T> {
     Nil,
     Atom(T),
-    List(Vec<Expression<T>>)
+    Str(String),
+    Num(Number),
+    List(Vec<Expression<T>>),
+    Closure(::std::rc::Rc<Closure>),
+    Native(::std::rc::Rc<Native>),
+    /// A macro defined with `defmacro`. The closure is called with the
+    /// unevaluated forms a use of the macro is given, and returns the code
+    /// to evaluate in its place.
+    Macro(::std::rc::Rc<Closure>),
+    /// A macro defined with `define-syntax`.
+    Syntax(::std::rc::Rc<SyntaxRules>),
+}
+
+/// The value of a `lambda` or `label` expression: the function's parameters
+/// and body, together with the environment it was defined in.
+pub struct Closure {
+    pub params: Vec<String>,
+    /// The expressions of the body, which are evaluated in order.
+    pub body: Vec<Expression<String>>,
+    pub env: Env,
+    pub sym: Option<String>, // lambdas will have None, labels will have Some
+}
+
+impl Closure {
+    /// The `lambda` expression this closure was made from, wrapped in a
+    /// `label` if it has a name. Symbols introduced by hygienic macros are
+    /// renamed to ones that can be read back in.
+    pub fn literal(&self) -> Expression<String> {
+        let params = self.params.iter().map(|p| Expression::Atom(p.clone())).collect();
+        let mut lambda = vec!(Expression::Atom("lambda".to_string()), Expression::List(params));
+        lambda.extend(self.body.iter().cloned());
+        let lambda = Expression::List(lambda);
+        syntax::readable(&match self.sym {
+            Some(ref s) => Expression::List(vec!(Expression::Atom("label".to_string()),
+                                                 Expression::Atom(s.clone()),
+                                                 lambda)),
+            None => lambda,
+        })
+    }
+
+    /// The `defun` expression that defines this closure, if it has a name.
+    pub fn defun(&self) -> Option<Expression<String>> {
+        self.definition("defun")
+    }
+
+    /// The `defmacro` expression that defines a macro with this closure as
+    /// its expander, if it has a name.
+    pub fn defmacro(&self) -> Option<Expression<String>> {
+        self.definition("defmacro")
+    }
+
+    fn definition(&self, form: &str) -> Option<Expression<String>> {
+        let params = self.params.iter().map(|p| Expression::Atom(p.clone())).collect();
+        self.sym.as_ref().map(|s| {
+            let mut def = vec!(Expression::Atom(form.to_string()), Expression::Atom(s.clone()), Expression::List(params));
+            def.extend(self.body.iter().cloned());
+            syntax::readable(&Expression::List(def))
+        })
+    }
+}
+
+/// Two closures are equal if they are the same code, closed over the same
+/// environment. Looking up a closure that is bound in the environment it was
+/// made in gives a new `Closure` each time, and these are all equal.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        ptr::eq(self, other)
+            || (self.env.same(&other.env) && self.sym == other.sym
+                && self.params == other.params && self.body == other.body)
+    }
+}
+
+/// The signature of procedures implemented in Rust. They are passed the
+/// values of their arguments.
+pub type NativeFn = fn(&mut Interpreter, &[Expression<String>]) -> EvalResult;
+
+/// A procedure implemented in Rust, as registered with
+/// `Interpreter::register`.
+pub struct Native {
+    pub name: String,
+    pub arity: Arity,
+    pub func: NativeFn,
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        ptr::eq(self, other)
+    }
 }
 
 impl<T: Eq> Expression<T> {
@@ -20,31 +115,49 @@ impl<T> Expression<T> {
     }
 
     pub fn is_nil(&self) -> bool {
-        match *self {
-            Expression::Nil => true,
-            _ => false,
-        }
+        matches!(*self, Expression::Nil)
     }
 
     pub fn is_atom(&self) -> bool {
-        match *self {
-            Expression::Atom(_) => true,
-            _       => false
-        }
+        matches!(*self, Expression::Atom(_))
+    }
+
+    pub fn is_str(&self) -> bool {
+        matches!(*self, Expression::Str(_))
+    }
+
+    pub fn is_num(&self) -> bool {
+        matches!(*self, Expression::Num(_))
     }
 
     pub fn is_list(&self) -> bool {
-        !self.is_atom()
+        matches!(*self, Expression::List(_))
+    }
+
+    pub fn is_closure(&self) -> bool {
+        matches!(*self, Expression::Closure(_))
+    }
+
+    pub fn is_native(&self) -> bool {
+        matches!(*self, Expression::Native(_))
     }
 
-    pub fn get_ref_atom<'a>(&'a self) -> &'a T {
+    pub fn is_macro(&self) -> bool {
+        matches!(*self, Expression::Macro(_))
+    }
+
+    pub fn is_syntax(&self) -> bool {
+        matches!(*self, Expression::Syntax(_))
+    }
+
+    pub fn get_ref_atom(&self) -> &T {
         match *self {
             Expression::Atom(ref val) => val,
             _         => panic!("called Expression::get_ref_atom() on non-Atom"),
         }
     }
 
-    pub fn get_ref_list<'a>(&'a self) -> &'a Vec<Expression<T>> {
+    pub fn get_ref_list(&self) -> &Vec<Expression<T>> {
         match *self {
             Expression::List(ref val) => val,
             _         => panic!("called Expression::get_ref_list() on non-List"),
@@ -70,29 +183,58 @@ impl<T> Expression<T> {
 
 impl<T: fmt::Display> Expression<T> {
     pub fn print(&self) {
-        self.print_expr();
-        println!("");
-
+        println!("{}", self);
     }
+}
 
-    fn print_expr(&self) {
+impl<T: fmt::Display> fmt::Display for Expression<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Expression::Nil => {},
-            Expression::Atom(ref val) => { print!("{}", *val); },
+            Expression::Nil => Ok(()),
+            Expression::Atom(ref val) => write!(f, "{}", *val),
+            Expression::Num(ref n) => write!(f, "{}", n),
+            Expression::Str(ref s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            },
             Expression::List(ref vec) => {
-                print!("(");
-                if vec.len() > 0 {
+                write!(f, "(")?;
+                if !vec.is_empty() {
                     let mut vec_iter = vec.iter();
                     let first = vec_iter.next();
-                    first.unwrap().print_expr();
+                    write!(f, "{}", first.unwrap())?;
 
                     for e in vec_iter {
-                        print!(" ");
-                        e.print_expr();
+                        write!(f, " {}", e)?;
                     }
                 }
-                print!(")");
+                write!(f, ")")
             },
+            Expression::Closure(ref c) => {
+                match c.sym {
+                    Some(ref s) => write!(f, "#<function {}>", syntax::alias_base(s)),
+                    None => write!(f, "#<lambda>"),
+                }
+            },
+            Expression::Native(ref n) => write!(f, "#<builtin {}>", n.name),
+            Expression::Macro(ref m) => write!(f, "#<macro {}>", m.sym.as_ref().map_or("", |s| syntax::alias_base(s))),
+            Expression::Syntax(ref s) => write!(f, "#<syntax {}>", s.name),
         }
     }
 }
+
+impl<T: fmt::Display> fmt::Debug for Expression<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,198 @@
+//! The interpreter: the global environment, and the native procedures that
+//! are registered in it. This is the entry point for embedding radicle.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+use super::{Expr, Env, Expression, Native, NativeFn, Nil, List};
+use error::{Error, Arity};
+use eval::{eval, eval_ref, EvalResult};
+use read::read;
+use builtins;
+use stack;
+
+/// How deeply `eval` may recurse by default. In optimized builds, this is low
+/// enough that the interpreter runs out of depth before a thread with a 2MB
+/// stack (the default for threads other than the main one) runs out of stack.
+/// Unoptimized builds use several times as much stack per level, so where
+/// the stack's size can be found out, `eval` also stops when it is nearly
+/// used up.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+// How much stack `eval` leaves unused, for what happens between one level of
+// evaluation and the next, like the calls of native procedures.
+const STACK_RESERVE: usize = 256 * 1024;
+
+pub struct Interpreter {
+    env: Env,
+    // the number of nested calls to `eval` in progress, and the limit on it
+    depth: usize,
+    max_depth: usize,
+    // the names of the procedures whose bodies are being evaluated, outermost
+    // first
+    calls: Vec<String>,
+    // how many hygienic macro expansions there have been, to tell the
+    // symbols each one introduces apart
+    expansions: usize,
+    // the addresses of the expressions whose evaluation failed along with the
+    // last error, innermost first, for `error_path`
+    trace: Vec<usize>,
+}
+
+impl Interpreter {
+    /// Creates an interpreter whose global environment holds the builtin
+    /// procedures.
+    pub fn new() -> Interpreter {
+        let mut interp = Interpreter { env: Env::new(), depth: 0, max_depth: DEFAULT_MAX_DEPTH, calls: vec!(), expansions: 0, trace: vec!() };
+        builtins::register_all(&mut interp);
+        interp
+    }
+
+    /// The global environment.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// Removes every global definition, leaving only the builtin procedures.
+    /// Closures that were made before keep working, but no longer see the
+    /// global definitions they may have relied on.
+    pub fn reset(&mut self) {
+        self.env.clear();
+        builtins::register_all(self);
+    }
+
+    /// How deeply evaluation may recurse before failing with
+    /// `Error::RecursionLimit`.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Sets how deeply evaluation may recurse. Each procedure call that is
+    /// not a tail call takes at least one level, and each level takes around
+    /// a kilobyte of stack in optimized builds, so raising the limit far above
+    /// the default may also require running the interpreter on a thread with
+    /// a larger stack. Evaluation fails when the stack is nearly used up
+    /// whatever the limit is, on the platforms where radicle can tell.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Binds `name` in the global environment to a procedure implemented by
+    /// `func`. `func` is only ever called with a number of (evaluated)
+    /// arguments that `arity` accepts.
+    pub fn register(&mut self, name: &str, arity: Arity, func: NativeFn) {
+        let native = Native { name: name.to_string(), arity, func };
+        self.env.insert_global(name.to_string(), Expression::Native(Rc::new(native)));
+    }
+
+    /// Binds `name` to `val` in the global environment.
+    pub fn define<V: Into<Expr>>(&mut self, name: &str, val: V) {
+        self.env.insert_global(name.to_string(), val.into());
+    }
+
+    /// Looks up `name` in the global environment.
+    pub fn get(&self, name: &str) -> Option<Expr> {
+        self.env.find_copy(name)
+    }
+
+    /// Evaluates an expression in the global environment.
+    pub fn eval(&mut self, expr: Expr) -> EvalResult {
+        let env = self.env.clone();
+        eval(self, &env, expr)
+    }
+
+    /// Like `eval`, but keeps track of which subexpression of `expr` failed,
+    /// if evaluation fails, for `error_path` to find.
+    pub fn eval_traced(&mut self, expr: &Expr) -> EvalResult {
+        let env = self.env.clone();
+        eval_ref(self, &env, expr)
+    }
+
+    /// Where in `expr` the last call to `eval_traced`, which must have been
+    /// given `expr`, failed: the path to the innermost subexpression that
+    /// failed, as the indices of the list elements to go into from `expr`
+    /// down. Only the subexpressions of `expr` itself count, so when an error
+    /// comes from the body of a procedure, the path is to the call.
+    pub fn error_path(&self, expr: &Expr) -> Vec<usize> {
+        self.trace.iter().filter_map(|&addr| path_to(expr, addr)).next().unwrap_or_default()
+    }
+
+    /// Reads and evaluates every expression in `src`, returning the value of
+    /// the last one (or `Nil` if there are none). Evaluation stops at the
+    /// first error.
+    pub fn eval_str(&mut self, src: &str) -> EvalResult {
+        let mut res = Nil;
+        for expr in read(src)? {
+            res = self.eval(expr)?;
+        }
+        Ok(res)
+    }
+
+    /// Like `eval_str`, but reads the program from a file.
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> EvalResult {
+        let path = path.as_ref();
+        let io_error = |e: ::std::io::Error| Error::Io { path: path.display().to_string(), reason: e.to_string() };
+
+        let mut src = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut src)).map_err(io_error)?;
+        self.eval_str(&src)
+    }
+}
+
+// Bookkeeping for `eval`, which calls `enter` when it starts evaluating an
+// expression, `call` whenever that evaluation continues into the body of a
+// procedure, and `leave` when it is done.
+impl Interpreter {
+    pub(crate) fn enter(&mut self) -> Result<usize, Error> {
+        let out_of_stack = stack::remaining().is_some_and(|left| left < STACK_RESERVE);
+        if self.depth >= self.max_depth || out_of_stack {
+            return Err(Error::RecursionLimit { limit: self.depth, calls: self.calls.clone() });
+        }
+        if self.depth == 0 {
+            self.trace.clear();
+        }
+        self.depth += 1;
+        Ok(self.calls.len())
+    }
+
+    pub(crate) fn call(&mut self, base: usize, name: String) {
+        self.calls.truncate(base);
+        self.calls.push(name);
+    }
+
+    pub(crate) fn leave(&mut self, base: usize) {
+        self.calls.truncate(base);
+        self.depth -= 1;
+    }
+
+    // Records that evaluating `expr` failed.
+    pub(crate) fn trace(&mut self, expr: &Expr) {
+        self.trace.push(expr as *const Expr as usize);
+    }
+
+    pub(crate) fn fresh_id(&mut self) -> usize {
+        self.expansions += 1;
+        self.expansions
+    }
+}
+
+// The path from `expr` to the subexpression at `addr`, if it is one.
+fn path_to(expr: &Expr, addr: usize) -> Option<Vec<usize>> {
+    if expr as *const Expr as usize == addr {
+        return Some(vec!());
+    }
+    match *expr {
+        List(ref vec) => vec.iter().enumerate().filter_map(|(i, e)| {
+            path_to(e, addr).map(|mut path| { path.insert(0, i); path })
+        }).next(),
+        _ => None,
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
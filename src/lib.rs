@@ -0,0 +1,60 @@
+//! A lisp interpreter.
+//!
+//! To embed radicle, create an `Interpreter`, give it any native procedures
+//! your application provides, and evaluate code with it:
+//!
+//! ```
+//! use std::convert::TryFrom;
+//! use radicle::{Interpreter, Expr, EvalResult};
+//! use radicle::error::Arity;
+//!
+//! fn double(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+//!     let n = i64::try_from(args[0].clone())?;
+//!     Ok(Expr::from(n * 2))
+//! }
+//!
+//! let mut interp = Interpreter::new();
+//! interp.register("double", Arity::Exactly(1), double);
+//! interp.define("x", 20);
+//!
+//! let val = interp.eval_str("(+ (double x) 2)").unwrap();
+//! assert_eq!(i64::try_from(val).unwrap(), 42);
+//! ```
+
+#![crate_name = "radicle"]
+
+extern crate libc;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+
+use std::collections::HashMap;
+use std::vec::IntoIter;
+
+pub use expr::{Expression, Closure, Native, NativeFn};
+pub use expr::Expression::{Nil, Atom, List, Str, Num};
+pub use num::Number;
+pub use syntax::SyntaxRules;
+pub use env::Env;
+pub use error::Error;
+pub use eval::EvalResult;
+pub use interp::Interpreter;
+
+pub mod builtins;
+pub mod convert;
+pub mod env;
+pub mod error;
+pub mod expr;
+pub mod interp;
+pub mod num;
+pub mod eval;
+pub mod read;
+pub mod span;
+mod stack;
+pub mod syntax;
+#[cfg(test)]
+mod test;
+
+/// The representation of Lisp expressions
+pub type Expr = Expression<String>;
@@ -1,48 +1,89 @@
-//! A lisp interpreter.
+//! The radicle REPL and file runner.
 
-#![crate_name = "radicle"]
+extern crate libc;
+extern crate radicle;
+
+mod complete;
+mod editor;
+#[cfg(test)]
+mod repl_test;
 
-pub use std::collections::HashMap;
-pub use std::vec::IntoIter;
-use std::env;
 use std::fs::File;
 use std::path::Path;
-use std::io::{stdin, stdout, BufRead, BufReader, Write, Read};
-
-pub use expr::Expression;
-pub use expr::Expression::{Nil, Atom, List};
+use std::process;
+use std::thread;
+use std::time::Instant;
+use std::io::{Read, Write};
+
+use radicle::{Interpreter, Expression, Nil};
+use radicle::eval::SPECIAL_FORMS;
+use radicle::read::{read_spanned, needs_more_input};
+
+use complete::EnvCompleter;
+use editor::{Editor, Input};
+
+// Deep recursion in radicle code means deep recursion in the interpreter, so
+// it runs on a thread with a much larger stack than the main thread's, which
+// leaves room for a much higher recursion limit than the default.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+const MAX_DEPTH: usize = 20_000;
+
+// The REPL's history is kept in this file in the user's home directory.
+const HISTORY_FILE: &str = ".radicle_history";
+
+const COMMANDS: &str = "\
+:env          list the global bindings
+:doc sym      describe what sym is bound to
+:load file    evaluate the expressions in a file
+:save file    write every defun and macro out to a file
+:time expr    evaluate expr and show how long it took
+:reset        forget every definition
+:quit         leave the REPL
+:help         show this list";
 
-use eval::eval;
-use read::read;
+fn main() {
+    let child = thread::Builder::new().stack_size(STACK_SIZE).spawn(run)
+                                      .expect("Couldn't start the interpreter thread.");
+    // the panic has already been reported by the thread that panicked, so
+    // just exit the way a program that panics on its main thread does
+    if child.join().is_err() {
+        process::exit(101);
+    }
+}
 
-pub mod expr;
-pub mod eval;
-pub mod read;
-mod test;
+fn new_interpreter() -> Interpreter {
+    let mut interp = Interpreter::new();
+    interp.set_max_depth(MAX_DEPTH);
+    interp
+}
 
-fn main() {
-    let mut args = env::args();
+fn run() {
+    let mut args = std::env::args();
     if args.len() == 1 {
         repl();
     } else if args.len() > 2 {
         println!("radicle: Only one argument allowed.");
     } else {
         args.next();
-        interpret_file(args.next().unwrap().clone());
+        interpret_file(args.next().unwrap());
     }
 }
 
 pub fn interpret_file(fname: String) {
-    let path = Path::new(&fname);
+    load_file(&fname, &mut new_interpreter());
+}
+
+fn load_file(fname: &str, interp: &mut Interpreter) {
+    let path = Path::new(fname);
 
     if path.is_file() {
-        let mut hw_file = File::open(&path).expect("Couldn't open file to interpret it.");
+        let mut hw_file = File::open(path).expect("Couldn't open file to interpret it.");
 
         let mut program_text = String::new();
         match hw_file.read_to_string(&mut program_text) {
             Err(e) => println!("{}", e),
             Ok(_) => {
-                read_eval(program_text, &mut Env::new());
+                read_eval(program_text, interp);
             }
         }
     } else {
@@ -51,50 +92,155 @@ pub fn interpret_file(fname: String) {
 }
 
 pub fn repl() {
-    let mut env = Env::new();
-    let mut stdin = BufReader::new(stdin());
-    let mut stdout = stdout();
-    print!("repl> ");
-    stdout.flush();
-    for line in stdin.lines() {
-        read_eval(line.unwrap(), &mut env);
-
-        print!("repl> ");
-        stdout.flush();
+    let mut interp = new_interpreter();
+    let mut editor = Editor::new();
+    editor.set_completer(EnvCompleter::new(interp.env().clone()));
+    if let Some(home) = std::env::var_os("HOME") {
+        if let Err(e) = editor.set_history_file(Path::new(&home).join(HISTORY_FILE)) {
+            println!("radicle: couldn't read history: {}", e);
+        }
     }
 
-}
-
-/// A convenience function that calls read & eval and displays their results
-pub fn read_eval(s: String, env: &mut Env) {
-    match read(s.as_ref()) {
-        Err(e) => println!("\nParse error: {}", e),
-        Ok(parsed) => {
-            for expr in parsed.into_iter() {
-                match eval(env, expr) {
-                    Ok(Nil) => {},
-                    Ok(expr) => expr.print(),
-                    Err(ref x) => println!("\nError: {}", *x),
+    // Lines are collected until they make up whole expressions, so that
+    // multi-line definitions can be typed or pasted in.
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "repl> " } else { "...> " };
+        match editor.read_line(prompt) {
+            Ok(Input::Line(ref line)) if input.is_empty() && line.trim_start().starts_with(':') => {
+                if !run_command(line, &mut interp) {
+                    return;
                 }
-            }
+            },
+            Ok(Input::Line(line)) => {
+                input.push_str(&line);
+                input.push('\n');
+
+                if !needs_more_input(&input) {
+                    read_eval(input, &mut interp);
+                    input = String::new();
+                }
+            },
+            Ok(Input::Interrupted) => input = String::new(),
+            Ok(Input::Eof) => break,
+            Err(e) => {
+                println!("radicle: {}", e);
+                break;
+            },
         }
     }
+
+    if !input.trim().is_empty() {
+        read_eval(input, &mut interp);
+    }
 }
 
-/// The representation of Lisp expressions
-pub type Expr = Expression<String>;
+/// Runs a REPL command, like `:load foo.rad`. Returns false if the REPL
+/// should exit.
+fn run_command(line: &str, interp: &mut Interpreter) -> bool {
+    let line = line.trim();
+    let (cmd, arg) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+
+    match (cmd, arg) {
+        (":quit", "") => return false,
+        (":help", "") => println!("Commands:\n{}", COMMANDS),
+        (":env", "") => show_env(interp),
+        (":reset", "") => interp.reset(),
+        (":doc", sym) if !sym.is_empty() => show_doc(sym, interp),
+        (":load", fname) if !fname.is_empty() => load_file(fname, interp),
+        (":save", fname) if !fname.is_empty() => save_defuns(fname, interp),
+        (":time", expr) if !expr.is_empty() => {
+            let start = Instant::now();
+            read_eval(expr.to_string(), interp);
+            println!("Time: {:?}", start.elapsed());
+        },
+        _ => println!("Unknown command: {}. Commands:\n{}", line, COMMANDS),
+    }
+    true
+}
+
+fn show_env(interp: &Interpreter) {
+    let mut names = interp.env().names();
+    names.sort();
+    for name in names {
+        println!("{} = {}", name, interp.get(&name).unwrap());
+    }
+}
 
-#[derive(Clone)]
-pub struct Env {
-    bindings: HashMap<String, Expr>,
+fn show_doc(sym: &str, interp: &Interpreter) {
+    if let Some(&(_, usage)) = SPECIAL_FORMS.iter().find(|&&(name, _)| name == sym) {
+        println!("{} is a special form: {}", sym, usage);
+        return;
+    }
+
+    match interp.get(sym) {
+        None => println!("{} is not bound", sym),
+        Some(Expression::Closure(ref c)) => {
+            println!("{} is a procedure:\n{}", sym, c.defun().unwrap_or_else(|| c.literal()));
+        },
+        Some(Expression::Macro(ref m)) => {
+            println!("{} is a macro:\n{}", sym, m.defmacro().unwrap_or_else(|| m.literal()));
+        },
+        Some(Expression::Syntax(ref rules)) => {
+            println!("{} is a macro:\n{}", sym, rules.definition());
+        },
+        Some(Expression::Native(ref n)) => {
+            println!("{} is a builtin procedure taking {}", sym, n.arity);
+        },
+        Some(val) => println!("{} is {}", sym, val),
+    }
 }
 
-impl Env {
-    fn new() -> Env {
-        Env { bindings: HashMap::new() }
+// Writes out the source of every procedure and macro defined with `defun`,
+// `defmacro` or `define-syntax`, or at least those still bound to the name
+// they were defined with.
+fn save_defuns(fname: &str, interp: &Interpreter) {
+    let mut names = interp.env().names();
+    names.sort();
+
+    let mut src = String::new();
+    let mut count = 0;
+    for name in names {
+        let definition = match interp.get(&name) {
+            Some(Expression::Closure(ref c)) if c.sym.as_ref() == Some(&name) => c.defun(),
+            Some(Expression::Macro(ref m)) if m.sym.as_ref() == Some(&name) => m.defmacro(),
+            Some(Expression::Syntax(ref rules)) if rules.name == name => Some(rules.definition()),
+            _ => None,
+        };
+        if let Some(definition) = definition {
+            src.push_str(&format!("{}\n\n", definition));
+            count += 1;
+        }
+    }
+
+    match File::create(fname).and_then(|mut f| f.write_all(src.as_bytes())) {
+        Ok(()) => println!("Saved {} definitions to {}", count, fname),
+        Err(e) => println!("radicle: can't write {}: {}", fname, e),
     }
+}
 
-    fn find_copy(&self, key: &String) -> Option<Expr> {
-        self.bindings.get(key).cloned()
+/// A convenience function that calls read & eval and displays their results
+pub fn read_eval(s: String, interp: &mut Interpreter) {
+    match read_spanned(s.as_ref()) {
+        Err(e) => println!("\nParse error: {}", e),
+        Ok(parsed) => {
+            for (expr, spans) in parsed.into_iter() {
+                match interp.eval_traced(&expr) {
+                    Ok(Nil) => {},
+                    Ok(expr) => expr.print(),
+                    Err(x) => {
+                        println!("\nError: {}", x);
+
+                        // Point at the subexpression that failed.
+                        let span = spans.at(&interp.error_path(&expr)).unwrap_or(spans.span);
+                        println!("  at line {}, column {}:\n{}",
+                                 span.start.line, span.start.column, span.show(&s));
+                    },
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,248 @@
+//! Numbers: fixed-size integers that are promoted to arbitrary precision
+//! integers when they overflow, exact rationals, and floats.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero, One, Signed};
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Number {
+    Int(i64),
+    /// An integer that does not fit in an `i64`. Integers are always kept in
+    /// the smallest representation they fit in, so two equal integers are
+    /// always represented the same way.
+    Big(BigInt),
+    /// A rational that is not an integer, always in lowest terms.
+    Ratio(BigRational),
+    Float(f64),
+}
+
+use self::Number::{Int, Big, Ratio, Float};
+
+impl Number {
+    /// Parses a number literal: an integer like `-42`, a rational like `2/3`,
+    /// or a float like `1.5`, `.5` or `6.02e23`. Anything else (including
+    /// `inf` and `nan`, which Rust would otherwise accept, and floats too large
+    /// to represent) is not a number.
+    pub fn parse(s: &str) -> Option<Number> {
+        if let Some((numer, denom)) = s.split_once('/') {
+            if !denom.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            return match (Number::parse(numer), denom.parse::<BigInt>()) {
+                (Some(n), Ok(ref d)) if n.is_integer() && !d.is_zero() => {
+                    Some(Number::from_rational(BigRational::new(n.to_bigint(), d.clone())))
+                },
+                _ => None,
+            };
+        }
+
+        if !Number::is_decimal(s) {
+            return None;
+        }
+
+        let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+        if digits.chars().all(|c| c.is_ascii_digit()) {
+            match s.parse::<i64>() {
+                Ok(i) => Some(Int(i)),
+                Err(_) => s.parse::<BigInt>().ok().map(Big),
+            }
+        } else {
+            s.parse::<f64>().ok().filter(|f| f.is_finite()).map(Float)
+        }
+    }
+
+    /// Whether `s` is written like a float too large to represent, like
+    /// `1e400`. `parse` doesn't accept these, but they aren't symbols either.
+    pub fn is_out_of_range(s: &str) -> bool {
+        Number::is_decimal(s) && s.parse::<f64>().is_ok_and(|f| f.is_infinite())
+    }
+
+    // Whether `s` is made of the characters of a decimal integer or float,
+    // with at least one digit.
+    fn is_decimal(s: &str) -> bool {
+        let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+        digits.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+            && digits.contains(|c: char| c.is_ascii_digit())
+            && digits.chars().all(|c| c.is_ascii_digit() || ".eE+-".contains(c))
+    }
+
+    /// Whether `s` is written like a rational whose denominator is zero, like
+    /// `1/0`. `parse` doesn't accept these, but they aren't symbols either.
+    pub fn is_zero_ratio(s: &str) -> bool {
+        match s.split_once('/') {
+            Some((numer, denom)) => {
+                !denom.is_empty() && denom.chars().all(|c| c == '0')
+                    && Number::parse(numer).is_some_and(|n| n.is_integer())
+            },
+            None => false,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(*self, Int(_) | Big(_))
+    }
+
+    /// Whether this is an exact number, i.e. an integer or a rational.
+    pub fn is_exact(&self) -> bool {
+        !matches!(*self, Float(_))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Int(i) => i == 0,
+            Big(ref b) => b.is_zero(),
+            Ratio(_) => false,
+            Float(f) => f == 0.0,
+        }
+    }
+
+    pub fn add(&self, other: &Number) -> Number {
+        self.combine(other, i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+
+    pub fn sub(&self, other: &Number) -> Number {
+        self.combine(other, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub fn mul(&self, other: &Number) -> Number {
+        self.combine(other, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+    }
+
+    /// Divides `self` by `other`. Dividing exact numbers gives an exact
+    /// result, so dividing integers that don't divide evenly gives a
+    /// rational. Dividing an exact number by exact zero gives `None`.
+    pub fn div(&self, other: &Number) -> Option<Number> {
+        if self.is_exact() && other.is_exact() {
+            if other.is_zero() {
+                None
+            } else {
+                Some(Number::from_rational(self.to_rational() / other.to_rational()))
+            }
+        } else {
+            Some(Float(self.to_f64() / other.to_f64()))
+        }
+    }
+
+    /// The numerator of an exact number, in lowest terms.
+    pub fn numerator(&self) -> Number {
+        Number::from_bigint(self.to_rational().numer().clone())
+    }
+
+    /// The (always positive) denominator of an exact number, in lowest terms.
+    pub fn denominator(&self) -> Number {
+        Number::from_bigint(self.to_rational().denom().clone())
+    }
+
+    /// Integer division, rounding towards zero. Both numbers must be
+    /// integers; dividing by zero gives `None`.
+    pub fn quotient(&self, other: &Number) -> Option<Number> {
+        if other.is_zero() {
+            return None;
+        }
+        match (self, other) {
+            (&Int(a), &Int(b)) if a.checked_div(b).is_some() => Some(Int(a / b)),
+            _ => Some(Number::from_bigint(self.to_bigint() / other.to_bigint())),
+        }
+    }
+
+    /// The remainder of integer division, with the sign of `other`. Both
+    /// numbers must be integers; dividing by zero gives `None`.
+    pub fn modulo(&self, other: &Number) -> Option<Number> {
+        if other.is_zero() {
+            return None;
+        }
+        match (self, other) {
+            (&Int(a), &Int(b)) if a.checked_rem(b).is_some() => Some(Int(a.mod_floor(&b))),
+            _ => Some(Number::from_bigint(self.to_bigint().mod_floor(&other.to_bigint()))),
+        }
+    }
+
+    /// Compares two numbers by value, regardless of their representation.
+    /// Comparisons involving NaN give `None`.
+    pub fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (&Int(a), &Int(b)) => Some(a.cmp(&b)),
+            _ if self.is_exact() && other.is_exact() => {
+                Some(self.to_rational().cmp(&other.to_rational()))
+            },
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+
+    // Applies an arithmetic operation in the most precise representation
+    // that can hold both numbers: `i64` while the result fits, then big
+    // integers, then rationals, and floats if either number is inexact.
+    fn combine<I, R, F>(&self, other: &Number, int_op: I, ratio_op: R, float_op: F) -> Number
+        where I: Fn(i64, i64) -> Option<i64>,
+              R: Fn(BigRational, BigRational) -> BigRational,
+              F: Fn(f64, f64) -> f64 {
+        if let (&Int(a), &Int(b)) = (self, other) {
+            if let Some(i) = int_op(a, b) {
+                return Int(i);
+            }
+        }
+
+        if self.is_exact() && other.is_exact() {
+            Number::from_rational(ratio_op(self.to_rational(), other.to_rational()))
+        } else {
+            Float(float_op(self.to_f64(), other.to_f64()))
+        }
+    }
+
+    fn from_rational(r: BigRational) -> Number {
+        if r.denom().is_one() {
+            Number::from_bigint(r.to_integer())
+        } else {
+            Ratio(r)
+        }
+    }
+
+    fn to_rational(&self) -> BigRational {
+        match *self {
+            Ratio(ref r) => r.clone(),
+            _ => BigRational::from_integer(self.to_bigint()),
+        }
+    }
+
+    fn from_bigint(b: BigInt) -> Number {
+        match b.to_i64() {
+            Some(i) => Int(i),
+            None => Big(b),
+        }
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        match *self {
+            Int(i) => BigInt::from(i),
+            Big(ref b) => b.clone(),
+            Ratio(_) | Float(_) => panic!("called Number::to_bigint() on a non-integer"),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Int(i) => i as f64,
+            Big(ref b) => b.to_f64().unwrap_or(if b.is_negative() { f64::NEG_INFINITY } else { f64::INFINITY }),
+            Ratio(ref r) => r.to_f64().unwrap_or(f64::NAN),
+            Float(f) => f,
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Int(i) => write!(f, "{}", i),
+            Big(ref b) => write!(f, "{}", b),
+            Ratio(ref r) => write!(f, "{}", r),
+            // Debug formatting always includes a decimal point or exponent,
+            // so floats read back in as floats.
+            Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
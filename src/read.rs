@@ -1,87 +1,373 @@
 use std::iter::Peekable;
+use std::str::CharIndices;
 
-use super::{Expr, IntoIter, Atom, List};
+use super::{Expr, IntoIter, Atom, List, Str, Num, Number};
+use error::Error;
+use span::{Pos, Span, SpanTree};
+use syntax::ALIAS_SEP;
+
+pub type ReadResult = Result<(Expr, SpanTree), Error>;
+
+/// A token and the stretch of source text it was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    /// A delimiter, a quote, a datum comment or an atom.
+    Plain,
+    /// A string literal. The token's text is the string's contents, with any
+    /// escape sequences already replaced.
+    Str,
+}
+
+impl Token {
+    fn plain(text: String, start: Pos, end: Pos) -> Token {
+        Token { text, kind: TokenKind::Plain, span: Span { start, end } }
+    }
+}
 
 /// Intermediate representation after tokenization and before it gets read into
 /// an expression.
-pub type TokenStream = Peekable<IntoIter<String>>;
+pub struct TokenStream {
+    tokens: Peekable<IntoIter<Token>>,
+    // where the input ends, for reporting a premature end of input
+    end: Pos,
+}
+
+impl TokenStream {
+    pub fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek()
+    }
+
+    fn incomplete_at_end(&self) -> Error {
+        incomplete("Unexpected end of input", self.end)
+    }
+}
+
+impl Iterator for TokenStream {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.next()
+    }
+}
 
 /// Tries to read a string of symbols into a list of expressions
-pub fn read(s: &str) -> Result<Vec<Expr>, &'static str> {
-    let mut stream = tokenize(s);
+pub fn read(s: &str) -> Result<Vec<Expr>, Error> {
+    let exprs = read_spanned(s)?;
+    Ok(exprs.into_iter().map(|(expr, _)| expr).collect())
+}
+
+/// Like `read`, but also returns where in `s` each expression (and each of
+/// its subexpressions) came from.
+pub fn read_spanned(s: &str) -> Result<Vec<(Expr, SpanTree)>, Error> {
+    let mut stream = tokenize(s)?;
     let mut res = vec!();
 
-    while !stream.peek().is_none() {
-        match read_from(&mut stream) {
-            Err(e) => return Err(e),
-            Ok(x) => res.push(x),
+    loop {
+        skip_datum_comments(&mut stream)?;
+        if stream.peek().is_none() {
+            break;
         }
+        res.push(read_from(&mut stream)?);
     }
     Ok(res)
 }
 
-/// Turns a string into a stream of tokens. Currently assumes that tokens
-/// do not have spaces or parens/brackets/braces in them.
-pub fn tokenize(s: &str) -> TokenStream {
-    let mut s1 = s.replace("(", " ( ").replace(")", " ) ");
-    s1 = s1.replace("[", " [ ").replace("]", " ] ");
-    s1 = s1.replace("{", " { ").replace("}", " } ");
-    s1 = s1.replace("'", " ' ");
+/// Walks over source text one character at a time, keeping track of the
+/// position of the next character.
+struct Scanner<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    pos: Pos,
+}
 
-    let x: Vec<&str> = (&s1).split(|c: char| c.is_whitespace()).collect();
+impl<'a> Scanner<'a> {
+    fn new(s: &'a str) -> Scanner<'a> {
+        Scanner { chars: s.char_indices().peekable(), pos: Pos { offset: 0, line: 1, column: 1 } }
+    }
 
-    let mut ret: Vec<String> = vec!();
-    for &e in x.iter() {
-        if e != "" {
-            ret.push(e.to_string());
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// Consumes the next character, if it is `c`.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
         }
     }
 
-    ret.into_iter().peekable()
+    fn bump(&mut self) -> Option<char> {
+        let (offset, c) = self.chars.next()?;
+        self.pos.offset = offset + c.len_utf8();
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(c)
+    }
+}
+
+/// Turns a string into a stream of tokens. Tokens are separated by whitespace,
+/// and parens/brackets/braces, quotes, quasiquotes and unquotes (`,` and
+/// `,@`) are always tokens of their own.
+/// String literals are double-quoted, and may contain whitespace and parens.
+///
+/// Comments are dropped here: `;` comments out the rest of the line, and
+/// `#| ... |#` comments out everything between the markers (these may be
+/// nested). A datum comment, `#;`, comments out the expression that follows
+/// it, so it is passed on to the reader as a token.
+pub fn tokenize(s: &str) -> Result<TokenStream, Error> {
+    let mut ret: Vec<Token> = vec!();
+    let mut current: Option<(Pos, String)> = None;
+    let mut sc = Scanner::new(s);
+
+    loop {
+        let start = sc.pos;
+        let c = match sc.bump() {
+            None => break,
+            Some(c) => c,
+        };
+
+        let comment = c == ';' || (c == '#' && (sc.peek() == Some('|') || sc.peek() == Some(';')));
+        if c.is_whitespace() || is_delimiter(c) || c == '"' || comment {
+            if let Some((tok_start, text)) = current.take() {
+                ret.push(Token::plain(text, tok_start, start));
+            }
+        }
+
+        if c == ';' {
+            while sc.peek().is_some() && !sc.eat('\n') {
+                sc.bump();
+            }
+        } else if c == '#' && sc.eat('|') {
+            skip_block_comment(&mut sc, start)?;
+        } else if c == '#' && sc.eat(';') {
+            ret.push(Token::plain("#;".to_string(), start, sc.pos));
+        } else if c == '"' {
+            let text = read_string(&mut sc, start)?;
+            ret.push(Token { text, kind: TokenKind::Str, span: Span { start, end: sc.pos } });
+        } else if c == ',' && sc.eat('@') {
+            ret.push(Token::plain(",@".to_string(), start, sc.pos));
+        } else if is_delimiter(c) {
+            ret.push(Token::plain(c.to_string(), start, sc.pos));
+        } else if c == ALIAS_SEP {
+            // reserved for the symbols hygienic macros introduce
+            return Err(read_error("Unexpected NUL character".to_string(), start));
+        } else if !c.is_whitespace() {
+            match current {
+                Some((_, ref mut text)) => text.push(c),
+                None => current = Some((start, c.to_string())),
+            }
+        }
+    }
+
+    if let Some((start, text)) = current.take() {
+        ret.push(Token::plain(text, start, sc.pos));
+    }
+
+    Ok(TokenStream { tokens: ret.into_iter().peekable(), end: sc.pos })
+}
+
+/// Whether `s` ends partway through an expression: inside a list, a string
+/// literal or a block comment, or right after a quote. That is, whether
+/// reading it fails with `Error::Incomplete`. The REPL uses this to decide
+/// whether to keep reading lines before evaluating what it has. Input with
+/// other problems, like too many closing parens, is complete, and left for
+/// `read` to report.
+pub fn needs_more_input(s: &str) -> bool {
+    matches!(read(s), Err(Error::Incomplete { .. }))
+}
+
+/// Skips the rest of a block comment whose opening `#|` starts at `start`,
+/// including any comments nested inside it.
+fn skip_block_comment(sc: &mut Scanner, start: Pos) -> Result<(), Error> {
+    let mut depth = 1;
+    while depth > 0 {
+        match sc.bump() {
+            None => return Err(incomplete("Unterminated block comment", start)),
+            Some('|') if sc.eat('#') => depth -= 1,
+            Some('#') if sc.eat('|') => depth += 1,
+            Some(_) => {},
+        }
+    }
+    Ok(())
+}
+
+/// Reads the rest of a string literal whose opening `"` starts at `start`,
+/// returning its contents with escape sequences replaced.
+fn read_string(sc: &mut Scanner, start: Pos) -> Result<String, Error> {
+    let mut text = String::new();
+    loop {
+        let esc_start = sc.pos;
+        match sc.bump() {
+            None => return Err(incomplete("Unterminated string literal", start)),
+            Some('"') => return Ok(text),
+            Some('\\') => {
+                match sc.bump() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    Some('u') => text.push(read_unicode_escape(sc, esc_start)?),
+                    Some(c) => return Err(read_error(format!("Unknown escape sequence `\\{}`", c), esc_start)),
+                    None => return Err(incomplete("Unterminated string literal", start)),
+                }
+            },
+            Some(c) => text.push(c),
+        }
+    }
+}
+
+/// Reads the `{...}` part of a `\u{...}` escape that starts at `start`.
+fn read_unicode_escape(sc: &mut Scanner, start: Pos) -> Result<char, Error> {
+    let bad_escape = || read_error("Invalid unicode escape, expected \\u{<hex digits>}".to_string(), start);
+
+    if !sc.eat('{') {
+        return Err(bad_escape());
+    }
+    let mut digits = String::new();
+    while let Some(c) = sc.peek() {
+        if !c.is_ascii_hexdigit() {
+            break;
+        }
+        digits.push(c);
+        sc.bump();
+    }
+    if !sc.eat('}') || digits.is_empty() || digits.len() > 6 {
+        return Err(bad_escape());
+    }
+    u32::from_str_radix(&digits, 16).ok()
+        .and_then(::std::char::from_u32)
+        .ok_or_else(bad_escape)
 }
 
 /// Attempts to read an entire expression from the token stream. Detects
-/// mismatched parentheses. Also expands ' <expr> into (quote <expr)
-pub fn read_from(v: &mut TokenStream) -> Result<Expr, &'static str> {
+/// mismatched parentheses: a list must be closed by the delimiter that
+/// matches the one it was opened with. Also expands ' <expr> into
+/// (quote <expr>), and likewise ` into quasiquote, , into unquote and ,@ into
+/// unquote-splicing.
+pub fn read_from(v: &mut TokenStream) -> ReadResult {
     let tok = v.next();
     match tok {
-        None        => Err("Unexpected end of token stream"),
-        Some(s) =>
-            if is_beginning_list_sep(&s) {
+        None        => Err(v.incomplete_at_end()),
+        Some(tok) =>
+            if tok.kind == TokenKind::Str {
+                Ok( (Str(tok.text), SpanTree::leaf(tok.span)) )
+            } else if is_beginning_list_sep(&tok.text) {
                 let mut ch = vec!();
+                let mut spans = vec!();
                 loop {
-                    if is_end(v) { break; }
-                    match read_from(v) {
-                        Err(e) => { return Err(e); },
-                        Ok(expr) => { ch.push(expr); }
+                    skip_datum_comments(v)?;
+                    if v.peek().is_none() {
+                        return Err(incomplete("Unclosed list opened", tok.span.start));
                     }
+                    if is_end(v) { break; }
+                    let (expr, tree) = read_from(v)?;
+                    ch.push(expr);
+                    spans.push(tree);
                 }
 
-                v.next();
-                Ok( List(ch) )
-
-            } else if is_ending_list_sep(&s) {
-                Err("Unexpected list end token")
-            } else if "'" == s {
-                match read_from(v) {
-                    Err(e) => Err(e),
-                    Ok(expr) => Ok( List( vec!(Atom("quote".to_string()), expr)) ),
+                // the loop only ends at a closing delimiter
+                let end = v.next().unwrap();
+                if end.text != closing_sep(&tok.text) {
+                    let reason = format!("Mismatched `{}`: the `{}` at line {}, column {} must be closed by `{}`",
+                                         end.text, tok.text, tok.span.start.line, tok.span.start.column,
+                                         closing_sep(&tok.text));
+                    return Err(read_error(reason, end.span.start));
                 }
+                Ok( (List(ch), SpanTree { span: tok.span.to(end.span), children: spans }) )
+
+            } else if is_ending_list_sep(&tok.text) {
+                Err(read_error(format!("Unexpected list end token `{}`", tok.text), tok.span.start))
+            } else if let Some(name) = quote_name(&tok.text) {
+                let (expr, tree) = read_from(v)?;
+                let span = tok.span.to(tree.span);
+                Ok( (List( vec!(Atom(name.to_string()), expr)),
+                     SpanTree { span, children: vec!(SpanTree::leaf(tok.span), tree) }) )
+            } else if "#;" == tok.text {
+                read_from(v)?;
+                read_from(v)
             } else {
-                Ok( Atom(s) )
+                let expr = match Number::parse(&tok.text) {
+                    Some(n) => Num(n),
+                    None if Number::is_zero_ratio(&tok.text) => {
+                        let reason = format!("Zero denominator in `{}`", tok.text);
+                        return Err(read_error(reason, tok.span.start));
+                    },
+                    None if Number::is_out_of_range(&tok.text) => {
+                        let reason = format!("Float out of range in `{}`", tok.text);
+                        return Err(read_error(reason, tok.span.start));
+                    },
+                    None => Atom(tok.text),
+                };
+                Ok( (expr, SpanTree::leaf(tok.span)) )
             }
     }
 }
 
+/// Reads and throws away the expressions commented out by any datum comments
+/// at the front of the stream.
+fn skip_datum_comments(v: &mut TokenStream) -> Result<(), Error> {
+    while v.peek().is_some_and(|tok| tok.kind == TokenKind::Plain && tok.text == "#;") {
+        v.next();
+        read_from(v)?;
+    }
+    Ok(())
+}
+
+fn read_error(reason: String, pos: Pos) -> Error {
+    Error::Read { reason, line: pos.line, column: pos.column }
+}
+
+fn incomplete(reason: &str, pos: Pos) -> Error {
+    Error::Incomplete { reason: reason.to_string(), line: pos.line, column: pos.column }
+}
+
 fn is_end(v: &mut TokenStream) -> bool {
-    let x = v.peek();
-    x.is_some() && is_ending_list_sep(x.unwrap())
+    v.peek().is_some_and(|tok| tok.kind == TokenKind::Plain && is_ending_list_sep(&tok.text))
+}
+
+/// Whether `c` is a character that always ends an atom, and is a token of
+/// its own.
+pub fn is_delimiter(c: char) -> bool {
+    "()[]{}'`,".contains(c)
 }
 
-fn is_beginning_list_sep(s: &String) -> bool {
+/// The special form a quote-like token is shorthand for.
+fn quote_name(s: &str) -> Option<&'static str> {
+    match s {
+        "'" => Some("quote"),
+        "`" => Some("quasiquote"),
+        "," => Some("unquote"),
+        ",@" => Some("unquote-splicing"),
+        _ => None,
+    }
+}
+
+fn is_beginning_list_sep(s: &str) -> bool {
     "(" == s || "[" == s || "{" == s
 }
 
-fn is_ending_list_sep(s: &String) -> bool {
+fn is_ending_list_sep(s: &str) -> bool {
     ")" == s || "]" == s || "}" == s
 }
+
+/// The delimiter that closes a list opened with `open`.
+fn closing_sep(open: &str) -> &'static str {
+    match open {
+        "[" => "]",
+        "{" => "}",
+        _ => ")",
+    }
+}
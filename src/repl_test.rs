@@ -0,0 +1,151 @@
+use radicle::Interpreter;
+
+use complete::EnvCompleter;
+use editor::{Editor, Input, Completer};
+use save_defuns;
+
+// Feeds `keys` to the editor as if typed at a terminal.
+fn edit_line(editor: &mut Editor, keys: &str) -> Input {
+    let mut out = vec!();
+    editor.edit("> ", &mut keys.as_bytes(), &mut out).unwrap()
+}
+
+fn line(s: &str) -> Input {
+    Input::Line(s.to_string())
+}
+
+#[test]
+fn test_editor_keys() {
+    let mut editor = Editor::new();
+
+    assert_eq!(edit_line(&mut editor, "(+ 1 2)\r"), line("(+ 1 2)"));
+    // arrow keys, home and end
+    assert_eq!(edit_line(&mut editor, "ac\x1b[Db\x1b[H(\x1b[F)\r"), line("(abc)"));
+    // backspace and delete
+    assert_eq!(edit_line(&mut editor, "abcd\x7f\x01\x1b[3~\r"), line("bc"));
+    // emacs-style movement, killing and yanking
+    assert_eq!(edit_line(&mut editor, "foo bar\x02\x02\x0b\x01\x19\r"), line("arfoo b"));
+    assert_eq!(edit_line(&mut editor, "(foo bar-baz\x17qux\x1bbx\r"), line("(foo xqux"));
+    assert_eq!(edit_line(&mut editor, "one two\x15\x05 \x19\r"), line(" one two"));
+    // multi-byte characters
+    assert_eq!(edit_line(&mut editor, "\u{e9}t\u{e9}\x1b[D\x7f\r"), line("\u{e9}\u{e9}"));
+
+    // Ctrl-C abandons the line, and Ctrl-D or the end of input on an empty
+    // line ends input
+    assert_eq!(edit_line(&mut editor, "abc\x03"), Input::Interrupted);
+    assert_eq!(edit_line(&mut editor, "\x04"), Input::Eof);
+    assert_eq!(edit_line(&mut editor, ""), Input::Eof);
+    assert_eq!(edit_line(&mut editor, "ab\x01\x04"), line("b"));
+    assert_eq!(edit_line(&mut editor, "abc"), line("abc"));
+}
+
+#[test]
+fn test_editor_history() {
+    let mut editor = Editor::new();
+    edit_line(&mut editor, "first\r");
+    edit_line(&mut editor, "second\r");
+    edit_line(&mut editor, "second\r");
+    edit_line(&mut editor, "   \r");
+    assert_eq!(editor.history(), ["first", "second"]);
+
+    assert_eq!(edit_line(&mut editor, "\x1b[A\r"), line("second"));
+    assert_eq!(edit_line(&mut editor, "\x1b[A\x1b[A\x1b[A!\r"), line("first!"));
+    // going back down restores the line being typed
+    assert_eq!(edit_line(&mut editor, "new\x1b[A\x1b[A\x1b[B\x1b[B\r"), line("new"));
+    assert_eq!(editor.history(), ["first", "second", "first!", "new"]);
+
+    // reverse search
+    assert_eq!(edit_line(&mut editor, "\x12sec\r"), line("second"));
+    assert_eq!(edit_line(&mut editor, "\x12irst\x12\r"), line("first"));
+    assert_eq!(edit_line(&mut editor, "\x12fi\x1b[D?\r"), line("firs?t"));
+    // a failed search keeps the last match, and Ctrl-G cancels
+    assert_eq!(edit_line(&mut editor, "\x12newx\r"), line("new"));
+    assert_eq!(edit_line(&mut editor, "typed\x12sec\x07!\r"), line("typed!"));
+}
+
+#[test]
+fn test_editor_history_file() {
+    let path = ::std::env::temp_dir().join(format!("radicle_history_test_{}", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+
+    let mut editor = Editor::new();
+    editor.set_history_file(&path).unwrap();
+    edit_line(&mut editor, "(defun f (x) x)\r");
+    editor.add_history("(f 1)");
+
+    let mut editor = Editor::new();
+    editor.set_history_file(&path).unwrap();
+    assert_eq!(editor.history(), ["(defun f (x) x)", "(f 1)"]);
+    assert_eq!(edit_line(&mut editor, "\x1b[A\x1b[A\r"), line("(defun f (x) x)"));
+
+    ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_completion() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(defun foo-bar (x) x) (defun foo-baz (x) x)").unwrap();
+    let completer = EnvCompleter::new(interp.env().clone());
+    let complete = |line: &str| completer.complete(line, line.len());
+
+    // special forms and bound symbols, anywhere in an expression
+    assert_eq!(complete("(foo"), (1, vec!("foo-bar".to_string(), "foo-baz".to_string())));
+    assert_eq!(complete("(co"), (1, vec!("cond".to_string(), "cons".to_string())));
+    assert_eq!(complete("(cons 'a (foo-bar fi"), (18, vec!("first".to_string())));
+    assert_eq!(complete("(\"a string\" la"), (12, vec!("label".to_string(), "lambda".to_string())));
+    assert_eq!(complete("(nothing-like-this"), (1, vec!()));
+    assert_eq!(complete("("), (1, vec!()));
+
+    // later definitions are offered too
+    interp.eval_str("(defun food () 1)").unwrap();
+    assert_eq!(complete("(food").1, vec!("food".to_string()));
+
+    // file paths in the string given to `load`, and nothing in other strings
+    assert_eq!(complete("(load \"cod"), (7, vec!("code/".to_string())));
+    assert_eq!(complete("(load \"code/test_"),
+               (7, vec!("code/test_defun.rad".to_string(), "code/test_lambda.rad".to_string())));
+    assert_eq!(complete("(foo-bar \"cod"), (13, vec!()));
+
+    // completing in the middle of a line
+    assert_eq!(completer.complete("(foo- x)", 5), (1, vec!("foo-bar".to_string(), "foo-baz".to_string())));
+}
+
+#[test]
+fn test_editor_completion() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(defun foo-bar (x) x) (defun foo-baz (x) x)").unwrap();
+    let mut editor = Editor::new();
+    editor.set_completer(EnvCompleter::new(interp.env().clone()));
+
+    assert_eq!(edit_line(&mut editor, "(lam\t\r"), line("(lambda"));
+    assert_eq!(edit_line(&mut editor, "(fo\tr 1)\r"), line("(foo-bar 1)"));
+    assert_eq!(edit_line(&mut editor, "(fo x)\x1b[D\x1b[D\x1b[D\tz\r"), line("(foo-baz x)"));
+
+    // when the completions don't share any more of a prefix, they're listed
+    let mut out = vec!();
+    let res = editor.edit("> ", &mut "(foo-ba\t\r".as_bytes(), &mut out).unwrap();
+    assert_eq!(res, line("(foo-ba"));
+    assert!( String::from_utf8(out).unwrap().contains("foo-bar  foo-baz") );
+}
+
+#[test]
+fn test_save_load() {
+    let path = ::std::env::temp_dir().join(format!("radicle_save_test_{}.rad", ::std::process::id()));
+    let mut interp = Interpreter::new();
+    interp.eval_str("
+        (define-syntax def-twice
+          (syntax-rules ()
+            ((_ name f) (defun name (x) (let ((y (f x))) (f y))))))
+        (defun inc (y) (+ y 1))
+        (def-twice add-2 inc)").unwrap();
+    save_defuns(path.to_str().unwrap(), &interp);
+
+    // the symbols the macro introduced are saved so that they can be loaded
+    let src = ::std::fs::read_to_string(&path).unwrap();
+    assert!( !src.contains('\0') );
+    let mut interp = Interpreter::new();
+    interp.eval_str(&format!("(load {:?})", path.to_str().unwrap())).unwrap();
+    assert_eq!(format!("{}", interp.eval_str("(add-2 1)").unwrap()), "3");
+
+    ::std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,72 @@
+//! Source locations of tokens and expressions.
+
+/// A position in the source text: a byte offset, and the line and column
+/// (both starting at 1) it corresponds to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pos {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A stretch of source text, from `start` up to but not including `end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    /// The span that starts where `self` starts and ends where `other` ends.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+
+    /// The text of `src` that this span covers.
+    pub fn source<'a>(&self, src: &'a str) -> &'a str {
+        &src[self.start.offset..self.end.offset]
+    }
+
+    /// Renders the line this span starts on, with the spanned text underlined.
+    /// Spans covering several lines are underlined to the end of their first
+    /// line.
+    pub fn show(&self, src: &str) -> String {
+        let line_start = src[..self.start.offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[line_start..].find('\n').map_or(src.len(), |i| line_start + i);
+        let line = &src[line_start..line_end];
+
+        let indent = src[line_start..self.start.offset].chars().count();
+        let width = if self.end.line == self.start.line {
+            self.source(src).chars().count()
+        } else {
+            src[self.start.offset..line_end].chars().count()
+        };
+
+        format!("{}\n{}{}", line, " ".repeat(indent), "^".repeat(width.max(1)))
+    }
+}
+
+/// The spans of an expression that was read from source text. A `SpanTree`
+/// mirrors the structure of its expression: the tree for a list has one child
+/// per element of the list, while atoms have no children.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    pub fn leaf(span: Span) -> SpanTree {
+        SpanTree { span, children: vec!() }
+    }
+
+    /// The span of the subexpression at `path`, the indices of the list
+    /// elements to go into from the top of the tree down, as returned by
+    /// `Interpreter::eval_traced`.
+    pub fn at(&self, path: &[usize]) -> Option<Span> {
+        match path.split_first() {
+            None => Some(self.span),
+            Some((&i, rest)) => self.children.get(i).and_then(|child| child.at(rest)),
+        }
+    }
+}
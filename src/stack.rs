@@ -0,0 +1,58 @@
+//! How much of the current thread's stack is left. Deep recursion in radicle
+//! code means deep recursion in `eval`, and how much stack each level takes
+//! varies a lot between optimized and unoptimized builds, so a limit on the
+//! depth alone can't keep the interpreter from overflowing the stack.
+
+use std::cell::Cell;
+
+thread_local! {
+    // the lowest address of this thread's stack, once it has been looked up
+    static LIMIT: Cell<Option<Option<usize>>> = const { Cell::new(None) };
+}
+
+/// The number of bytes of stack the current thread has left, or `None` if
+/// that can't be found out on this platform.
+pub fn remaining() -> Option<usize> {
+    let marker = 0u8;
+    let sp = &marker as *const u8 as usize;
+    let limit = LIMIT.with(|limit| match limit.get() {
+        Some(l) => l,
+        None => {
+            let l = stack_limit();
+            limit.set(Some(l));
+            l
+        },
+    })?;
+    Some(sp.saturating_sub(limit))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn stack_limit() -> Option<usize> {
+    use std::{mem, ptr};
+
+    unsafe {
+        let mut attr: libc::pthread_attr_t = mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut addr = ptr::null_mut();
+        let mut size = 0;
+        let res = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        if res == 0 { Some(addr as usize) } else { None }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn stack_limit() -> Option<usize> {
+    unsafe {
+        let thread = libc::pthread_self();
+        let top = libc::pthread_get_stackaddr_np(thread) as usize;
+        Some(top - libc::pthread_get_stacksize_np(thread))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn stack_limit() -> Option<usize> {
+    None
+}
@@ -0,0 +1,373 @@
+//! Hygienic macros, defined with `define-syntax` and `syntax-rules`.
+//!
+//! Hygiene works by renaming. Every symbol that a template introduces (as
+//! opposed to one that came from the macro's arguments) is replaced by an
+//! alias that is unique to that expansion. So a binding made by the
+//! expansion can't capture the user's symbols, and an alias that the
+//! expansion doesn't bind refers to the global binding of the symbol it was
+//! made from, even where the user has bound that symbol locally. Special
+//! forms see through aliases, and `quote` turns them back into the symbols
+//! they were made from.
+
+use std::ptr;
+
+use super::{Expr, Atom, List, HashMap};
+use error::Error;
+use interp::Interpreter;
+
+/// Separates the name of a symbol from the number that makes an alias of it
+/// unique. The reader never produces symbols that contain it.
+pub const ALIAS_SEP: char = '\0';
+
+const ELLIPSIS: &str = "...";
+
+/// A macro defined with `syntax-rules`: patterns, each with the template to
+/// replace a use of the macro with if the use matches the pattern.
+pub struct SyntaxRules {
+    pub name: String,
+    /// Symbols that match only themselves in patterns, rather than binding
+    /// a pattern variable.
+    pub literals: Vec<String>,
+    pub rules: Vec<(Expr, Expr)>,
+}
+
+impl PartialEq for SyntaxRules {
+    fn eq(&self, other: &SyntaxRules) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// What a pattern variable matched: a single form, or, for a variable
+/// followed by an ellipsis, a sequence of matches.
+#[derive(Clone)]
+enum Binding {
+    One(Expr),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+impl SyntaxRules {
+    /// Parses `spec`, the `(syntax-rules (literals ...) (pattern template)
+    /// ...)` part of a `define-syntax` of `name`.
+    pub fn parse(name: &str, spec: &Expr) -> Result<SyntaxRules, Error> {
+        let bad_form = |reason| Error::BadForm { form: "syntax-rules".to_string(), reason, expr: spec.clone() };
+
+        let vec = match *spec {
+            List(ref vec) if vec.len() >= 2 && is_symbol("syntax-rules", &vec[0]) => vec,
+            _ => return Err(bad_form("expected (syntax-rules (literals ...) (pattern template) ...)")),
+        };
+
+        let literals = match vec[1] {
+            List(ref lits) if lits.iter().all(|l| l.is_atom()) => {
+                lits.iter().map(|l| l.get_ref_atom().clone()).collect()
+            },
+            _ => return Err(bad_form("the literals must be a list of symbols")),
+        };
+
+        let mut syntax = SyntaxRules { name: name.to_string(), literals, rules: vec!() };
+        for rule in &vec[2..] {
+            match *rule {
+                List(ref r) if r.len() == 2 && r[0].is_list() && !r[0].is_empty_list() => {
+                    let pats = &r[0].get_ref_list()[1..];
+                    if !is_valid_pattern(pats) {
+                        return Err(bad_form("an ellipsis must follow a pattern, at most once in each list"));
+                    }
+                    let vars: Vec<String> = pats.iter().flat_map(|p| syntax.pattern_vars(p)).collect();
+                    if vars.iter().enumerate().any(|(i, var)| vars[..i].contains(var)) {
+                        return Err(bad_form("a pattern variable can only appear once in a pattern"));
+                    }
+                    syntax.rules.push((r[0].clone(), r[1].clone()));
+                },
+                _ => return Err(bad_form("each rule must be a list of a pattern and a template")),
+            }
+        }
+
+        Ok(syntax)
+    }
+
+    /// The `define-syntax` expression that defines this macro.
+    pub fn definition(&self) -> Expr {
+        let mut spec = vec!(Atom("syntax-rules".to_string()),
+                            List(self.literals.iter().map(|l| Atom(l.clone())).collect()));
+        spec.extend(self.rules.iter().map(|(pat, tmpl)| List(vec!(pat.clone(), tmpl.clone()))));
+        readable(&List(vec!(Atom("define-syntax".to_string()), Atom(self.name.clone()), List(spec))))
+    }
+
+    /// Expands `form`, a use of this macro, with the first rule whose pattern
+    /// it matches. The keyword at the front of a pattern is ignored.
+    pub fn expand(&self, interp: &mut Interpreter, form: &Expr) -> Result<Expr, Error> {
+        let args = &form.get_ref_list()[1..];
+        for (pat, tmpl) in &self.rules {
+            let mut bindings = HashMap::new();
+            if self.match_list(&pat.get_ref_list()[1..], args, &mut bindings)? {
+                let id = interp.fresh_id();
+                return self.instantiate(tmpl, &bindings, id);
+            }
+        }
+        Err(self.error("no rule matches this use of the macro", form))
+    }
+
+    fn match_pattern(&self, pat: &Expr, form: &Expr, bindings: &mut Bindings) -> Result<bool, Error> {
+        match *pat {
+            Atom(ref p) if p == "_" => Ok(true),
+            Atom(ref p) if self.literals.contains(p) => {
+                Ok(matches!(*form, Atom(ref f) if alias_base(f) == p))
+            },
+            Atom(ref p) => {
+                bindings.insert(p.clone(), Binding::One(form.clone()));
+                Ok(true)
+            },
+            List(ref pats) => match *form {
+                List(ref forms) => self.match_list(pats, forms, bindings),
+                _ => Ok(false),
+            },
+            _ => Ok(*pat == *form),
+        }
+    }
+
+    fn match_list(&self, pats: &[Expr], forms: &[Expr], bindings: &mut Bindings) -> Result<bool, Error> {
+        let ellipsis = match pats.iter().position(is_ellipsis) {
+            None => {
+                if pats.len() != forms.len() {
+                    return Ok(false);
+                }
+                for (p, f) in pats.iter().zip(forms) {
+                    if !self.match_pattern(p, f, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                return Ok(true);
+            },
+            Some(i) => i,
+        };
+
+        // The pattern before the ellipsis matches any number of forms, and the
+        // patterns either side of it match the forms either side of those.
+        let (before, repeated, after) = (&pats[..ellipsis - 1], &pats[ellipsis - 1], &pats[ellipsis + 1..]);
+        if forms.len() < before.len() + after.len() {
+            return Ok(false);
+        }
+        let end = forms.len() - after.len();
+        if !self.match_list(before, &forms[..before.len()], bindings)?
+           || !self.match_list(after, &forms[end..], bindings)? {
+            return Ok(false);
+        }
+
+        let mut matches = vec!();
+        for form in &forms[before.len()..end] {
+            let mut m = HashMap::new();
+            if !self.match_pattern(repeated, form, &mut m)? {
+                return Ok(false);
+            }
+            matches.push(m);
+        }
+        for var in self.pattern_vars(repeated) {
+            let seq = matches.iter_mut().map(|m| m.remove(&var))
+                             .collect::<Option<_>>()
+                             .ok_or_else(|| self.error("a pattern variable can only appear once in a pattern", repeated))?;
+            bindings.insert(var, Binding::Many(seq));
+        }
+        Ok(true)
+    }
+
+    fn pattern_vars(&self, pat: &Expr) -> Vec<String> {
+        match *pat {
+            Atom(ref p) if p != "_" && p != ELLIPSIS && !self.literals.contains(p) => vec!(p.clone()),
+            List(ref pats) => pats.iter().flat_map(|p| self.pattern_vars(p)).collect(),
+            _ => vec!(),
+        }
+    }
+
+    fn instantiate(&self, tmpl: &Expr, bindings: &Bindings, id: usize) -> Result<Expr, Error> {
+        match *tmpl {
+            Atom(ref s) => match bindings.get(s) {
+                Some(Binding::One(form)) => Ok(form.clone()),
+                Some(Binding::Many(_)) => {
+                    Err(self.error("a pattern variable that matched a sequence must be followed by an ellipsis", tmpl))
+                },
+                None if s == ELLIPSIS => Err(self.error("an ellipsis must follow a template", tmpl)),
+                None => Ok(Atom(format!("{}{}{}", s, ALIAS_SEP, id))),
+            },
+            List(ref items) => {
+                let mut res = vec!();
+                let mut i = 0;
+                while i < items.len() {
+                    if items.get(i + 1).is_some_and(is_ellipsis) {
+                        self.instantiate_repeated(&items[i], bindings, id, &mut res)?;
+                        i += 2;
+                    } else {
+                        res.push(self.instantiate(&items[i], bindings, id)?);
+                        i += 1;
+                    }
+                }
+                Ok(List(res))
+            },
+            _ => Ok(tmpl.clone()),
+        }
+    }
+
+    // Instantiates a template that is followed by an ellipsis once for each
+    // form matched by the sequence variables in it.
+    fn instantiate_repeated(&self, tmpl: &Expr, bindings: &Bindings, id: usize, res: &mut Vec<Expr>) -> Result<(), Error> {
+        let mut seqs = vec!();
+        for var in template_symbols(tmpl) {
+            if let Some(Binding::Many(seq)) = bindings.get(&var) {
+                if !seqs.iter().any(|(v, _)| *v == var) {
+                    seqs.push((var, seq));
+                }
+            }
+        }
+
+        let len = match seqs.first() {
+            None => return Err(self.error("an ellipsis must follow a template with a pattern variable that matched a sequence", tmpl)),
+            Some(&(_, seq)) => seq.len(),
+        };
+        if seqs.iter().any(|&(_, seq)| seq.len() != len) {
+            return Err(self.error("pattern variables under the same ellipsis matched sequences of different lengths", tmpl));
+        }
+
+        let mut inner = bindings.clone();
+        for i in 0..len {
+            for &(ref var, seq) in &seqs {
+                inner.insert(var.clone(), seq[i].clone());
+            }
+            res.push(self.instantiate(tmpl, &inner, id)?);
+        }
+        Ok(())
+    }
+
+    fn error(&self, reason: &'static str, expr: &Expr) -> Error {
+        Error::BadForm { form: self.name.clone(), reason, expr: strip(expr) }
+    }
+}
+
+/// The symbol an alias was made from, or `s` itself if it is not an alias.
+pub fn alias_base(s: &str) -> &str {
+    match s.find(ALIAS_SEP) {
+        Some(i) => &s[..i],
+        None => s,
+    }
+}
+
+pub fn is_alias(s: &str) -> bool {
+    s.contains(ALIAS_SEP)
+}
+
+/// Replaces every alias in `expr` by the symbol it was made from.
+pub fn strip(expr: &Expr) -> Expr {
+    match *expr {
+        Atom(ref s) if is_alias(s) => Atom(alias_base(s).to_string()),
+        List(ref vec) => List(vec.iter().map(strip).collect()),
+        _ => expr.clone(),
+    }
+}
+
+/// `expr` with its aliases renamed so that it can be printed and read back
+/// in. An alias that `expr` binds, as a parameter or with a `let` say, is
+/// renamed to the symbol it was made from if that symbol doesn't otherwise
+/// appear in `expr`, and to a fresh symbol like `x-1` if it does. Any other
+/// alias refers to a global binding, and is replaced by the symbol it was
+/// made from.
+pub fn readable(expr: &Expr) -> Expr {
+    let mut bound = vec!();
+    binders(expr, &mut bound);
+
+    let mut taken: Vec<String> = template_symbols(expr).into_iter()
+        .filter(|s| !bound.contains(s))
+        .map(|s| alias_base(&s).to_string())
+        .collect();
+    let mut names = HashMap::new();
+    for alias in bound {
+        if names.contains_key(&alias) {
+            continue;
+        }
+        let base = alias_base(&alias);
+        let name = if taken.iter().any(|t| t == base) {
+            (1..).map(|n| format!("{}-{}", base, n)).find(|name| !taken.contains(name)).unwrap()
+        } else {
+            base.to_string()
+        };
+        taken.push(name.clone());
+        names.insert(alias, name);
+    }
+    rename(expr, &names)
+}
+
+// Adds the aliases that are bound in `expr` to `bound`.
+fn binders(expr: &Expr, bound: &mut Vec<String>) {
+    let vec = match *expr {
+        List(ref vec) if !vec.is_empty() => vec,
+        _ => return,
+    };
+    let mut add = |expr: &Expr| match *expr {
+        Atom(ref s) if is_alias(s) => bound.push(s.clone()),
+        List(ref items) => bound.extend(items.iter().filter_map(|i| match *i {
+            Atom(ref s) if is_alias(s) => Some(s.clone()),
+            _ => None,
+        })),
+        _ => {},
+    };
+    let binding_names = |bindings: &Expr| match *bindings {
+        List(ref bindings) => bindings.iter().filter_map(|b| match *b {
+            List(ref pair) if !pair.is_empty() => Some(pair[0].clone()),
+            _ => None,
+        }).collect(),
+        _ => vec!(),
+    };
+
+    let head = match vec[0] {
+        Atom(ref s) => alias_base(s),
+        _ => "",
+    };
+    match (head, vec.get(1), vec.get(2)) {
+        ("lambda", Some(params), _) | ("label", Some(params), _) | ("define", Some(params), _) => add(params),
+        ("defun", _, Some(params)) | ("defmacro", _, Some(params)) => add(params),
+        ("let", Some(name), Some(bindings)) if name.is_atom() => {
+            add(name);
+            add(&List(binding_names(bindings)));
+        },
+        ("let", Some(bindings), _) | ("let*", Some(bindings), _) | ("letrec", Some(bindings), _) => {
+            add(&List(binding_names(bindings)));
+        },
+        _ => {},
+    }
+    for item in vec {
+        binders(item, bound);
+    }
+}
+
+fn rename(expr: &Expr, names: &HashMap<String, String>) -> Expr {
+    match *expr {
+        Atom(ref s) if is_alias(s) => Atom(names.get(s).cloned().unwrap_or_else(|| alias_base(s).to_string())),
+        List(ref vec) => List(vec.iter().map(|e| rename(e, names)).collect()),
+        _ => expr.clone(),
+    }
+}
+
+fn is_symbol(name: &str, expr: &Expr) -> bool {
+    matches!(*expr, Atom(ref s) if alias_base(s) == name)
+}
+
+fn is_ellipsis(expr: &Expr) -> bool {
+    matches!(*expr, Atom(ref s) if s == ELLIPSIS)
+}
+
+/// Whether each ellipsis in a list of patterns follows a pattern, and no
+/// list has more than one.
+fn is_valid_pattern(pats: &[Expr]) -> bool {
+    let ellipses = pats.iter().filter(|p| is_ellipsis(p)).count();
+    !pats.first().is_some_and(is_ellipsis) && ellipses <= 1
+        && pats.iter().all(|p| match *p {
+            List(ref inner) => is_valid_pattern(inner),
+            _ => true,
+        })
+}
+
+fn template_symbols(tmpl: &Expr) -> Vec<String> {
+    match *tmpl {
+        Atom(ref s) => vec!(s.clone()),
+        List(ref items) => items.iter().flat_map(template_symbols).collect(),
+        _ => vec!(),
+    }
+}
@@ -1,6 +1,10 @@
 #![allow(unused_imports)]
-use eval::eval;
-use super::{HashMap, Env, Atom, List, Expr};
+use std::convert::TryFrom;
+use eval::{self, EvalResult};
+use read::{read, read_spanned, tokenize, needs_more_input};
+use error::Arity;
+use env::collect_cycles;
+use super::{HashMap, Env, Interpreter, Error, Number, Expression, Closure, Atom, List, Str, Num, Expr};
 
 fn make_atom(s: &str) -> Expr {
     Atom(s.to_string())
@@ -18,58 +22,77 @@ fn quote_expr(e: Expr) -> Expr {
     make_2list(make_atom("quote"), e)
 }
 
+fn read_one(s: &str) -> Expr {
+    read(s).unwrap().remove(0)
+}
+
+/// Evaluates `src` and checks that its value prints as `expected`.
+fn check(interp: &mut Interpreter, src: &str, expected: &str) {
+    assert_eq!(format!("{}", interp.eval_str(src).unwrap()), expected, "evaluating {}", src);
+}
+
+/// Checks that each of `srcs` is rejected as a malformed special form.
+fn check_malformed(interp: &mut Interpreter, srcs: &[&str]) {
+    for src in srcs {
+        match interp.eval_str(src) {
+            Err(Error::BadForm { .. }) | Err(Error::ArityMismatch { .. }) => {},
+            res => panic!("unexpected result for {}: {:?}", src, res),
+        }
+    }
+}
+
 #[test]
 fn test_eval_symbol() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     // symbol not found in env should be eval err
     let foo = make_atom("foo");
     let bar = make_atom("bar");
 
-    assert!( eval(&mut env, foo.clone()).is_err() );
+    assert!( interp.eval(foo.clone()).is_err() );
 
-    env.bindings.insert("foo".to_string(), bar.clone());
-    let foo_eval = eval(&mut env, foo.clone());
+    interp.env().insert("foo".to_string(), bar.clone());
+    let foo_eval = interp.eval(foo.clone());
     assert!( foo_eval.is_ok() && foo_eval.unwrap().eq(&bar) );
 }
 
 #[test]
 fn test_eval_empty_list() {
-    assert!( eval(&mut Env::new(), List(vec!())).is_err() );
+    assert!( Interpreter::new().eval(List(vec!())).is_err() );
 }
 
 #[test]
 fn test_eval_quote() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let nil = make_nil();
     let foo = make_atom("foo");
     let bar = make_atom("bar");
 
     let qnil = quote_expr(nil.clone());
-    let qnil_eval = eval(&mut env, qnil);
+    let qnil_eval = interp.eval(qnil);
     assert!( qnil_eval.is_ok() && qnil_eval.unwrap().eq(&nil) );
 
     let qfoo = quote_expr(foo.clone());
     let qfoo2 = qfoo.clone();
-    let qfoo_eval = eval(&mut env, qfoo);
+    let qfoo_eval = interp.eval(qfoo);
     assert!( qfoo_eval.is_ok() && qfoo_eval.unwrap().eq(&foo) );
 
     // "(quote foo)" should evaluate to "foo" regardless of what the symbol foo is
     // bound to in the environment
-    env.bindings.insert("foo".to_string(), bar.clone());
-    let qfoo2_eval = eval(&mut env, qfoo2);
+    interp.env().insert("foo".to_string(), bar.clone());
+    let qfoo2_eval = interp.eval(qfoo2);
     assert!( qfoo2_eval.is_ok() && qfoo2_eval.unwrap().eq(&foo) );
 
     let list = List(vec!(foo.clone(), bar.clone(), make_atom("baz")));
     let qlist = quote_expr(list.clone());
-    let qlist_eval = eval(&mut env, qlist);
+    let qlist_eval = interp.eval(qlist);
     assert!( qlist_eval.is_ok() && qlist_eval.unwrap().eq(&list) );
 }
 
 #[test]
 fn test_eval_atom() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let foo = make_atom("foo");
     let bar = make_atom("bar");
@@ -80,22 +103,22 @@ fn test_eval_atom() {
     let qfoo = quote_expr(foo.clone());
     let qnil = quote_expr(nil.clone());
 
-    let qfoo_eval = eval(&mut env, make_2list(atom.clone(), qfoo));
+    let qfoo_eval = interp.eval(make_2list(atom.clone(), qfoo));
     assert!( qfoo_eval.is_ok() && qfoo_eval.unwrap().eq(&t) );
 
-    let qnil_eval = eval(&mut env, make_2list(atom.clone(), qnil));
+    let qnil_eval = interp.eval(make_2list(atom.clone(), qnil));
     assert!( qnil_eval.is_ok() && qnil_eval.unwrap().eq(&t) );
 
     let list = make_2list(foo.clone(), bar.clone());
     let qlist = quote_expr(list.clone());
-    let qlist_eval = eval(&mut env, make_2list(atom.clone(), qlist));
+    let qlist_eval = interp.eval(make_2list(atom.clone(), qlist));
     assert!( qlist_eval.is_ok() && qlist_eval.unwrap().eq(&nil) );
 
 }
 
 #[test]
 fn test_eval_eq() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let foo = make_atom("foo");
     let bar = make_atom("bar");
@@ -106,23 +129,23 @@ fn test_eval_eq() {
     let qnil = quote_expr(nil.clone());
     let qfoo = quote_expr(foo.clone());
 
-    let eq_raw_sym_eval = eval(&mut env, List(vec!(eq.clone(), foo.clone(), foo.clone())));
+    let eq_raw_sym_eval = interp.eval(List(vec!(eq.clone(), foo.clone(), foo.clone())));
     assert!( eq_raw_sym_eval.is_err() );
 
-    let eq_diff_eval = eval(&mut env, List(vec!(eq.clone(), foo.clone(), bar.clone())));
-    assert!( eq_raw_sym_eval.is_err() );
+    let eq_diff_eval = interp.eval(List(vec!(eq.clone(), foo.clone(), bar.clone())));
+    assert!( eq_diff_eval.is_err() );
 
-    let eq_qnil_eval = eval(&mut env, List(vec!(eq.clone(), qnil.clone(), qnil.clone())));
+    let eq_qnil_eval = interp.eval(List(vec!(eq.clone(), qnil.clone(), qnil.clone())));
     assert!( eq_qnil_eval.is_ok() && eq_qnil_eval.unwrap().eq(&t) );
 
-    let eq_qfoo_eval = eval(&mut env, List(vec!(eq.clone(), qfoo.clone(), qfoo.clone())));
+    let eq_qfoo_eval = interp.eval(List(vec!(eq.clone(), qfoo.clone(), qfoo.clone())));
     assert!( eq_qfoo_eval.is_ok() && eq_qfoo_eval.unwrap().eq(&t) );
 
 }
 
 #[test]
 fn test_eval_first() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let foo = make_atom("foo");
     let bar = make_atom("bar");
@@ -132,22 +155,22 @@ fn test_eval_first() {
     let qfoo = quote_expr(foo.clone());
     let qnil = quote_expr(nil.clone());
 
-    let qfoo_eval = eval(&mut env, make_2list(first.clone(), qfoo));
+    let qfoo_eval = interp.eval(make_2list(first.clone(), qfoo));
     assert!( qfoo_eval.is_err() );
 
-    let qnil_eval = eval(&mut env, make_2list(first.clone(), qnil));
+    let qnil_eval = interp.eval(make_2list(first.clone(), qnil));
     assert!( qnil_eval.is_err() );
 
     let list = make_2list(foo.clone(), bar.clone());
     let qlist = quote_expr(list.clone());
-    let qlist_eval = eval(&mut env, make_2list(first.clone(), qlist));
+    let qlist_eval = interp.eval(make_2list(first.clone(), qlist));
     assert!( qlist_eval.is_ok() && qlist_eval.unwrap().eq(&foo) );
 
 }
 
 #[test]
 fn test_eval_rest() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let foo = make_atom("foo");
     let bar = make_atom("bar");
@@ -157,15 +180,15 @@ fn test_eval_rest() {
     let qfoo = quote_expr(foo.clone());
     let qnil = quote_expr(nil.clone());
 
-    let qfoo_eval = eval(&mut env, make_2list(rest.clone(), qfoo));
+    let qfoo_eval = interp.eval(make_2list(rest.clone(), qfoo));
     assert!( qfoo_eval.is_err() );
 
-    let qnil_eval = eval(&mut env, make_2list(rest.clone(), qnil));
+    let qnil_eval = interp.eval(make_2list(rest.clone(), qnil));
     assert!( qnil_eval.is_err() );
 
     let list = make_2list(foo.clone(), bar.clone());
     let qlist = quote_expr(list.clone());
-    let qlist_eval = eval(&mut env, make_2list(rest.clone(), qlist));
+    let qlist_eval = interp.eval(make_2list(rest.clone(), qlist));
 
     let list_foo = List(vec!(bar.clone()));
     assert!( qlist_eval.is_ok() && qlist_eval.unwrap().eq(&list_foo) );
@@ -173,7 +196,7 @@ fn test_eval_rest() {
 
 #[test]
 fn test_eval_cons() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let foo = make_atom("foo");
     let bar = make_atom("bar");
@@ -182,18 +205,18 @@ fn test_eval_cons() {
     let bar_list = List(vec!(bar.clone()));
     let qbar_list = quote_expr(bar_list.clone());
 
-    let bar_eval = eval(&mut env, List(vec!(cons.clone(), foo.clone(), bar.clone())));
+    let bar_eval = interp.eval(List(vec!(cons.clone(), foo.clone(), bar.clone())));
     assert!( bar_eval.is_err() );
 
     let qfoo = quote_expr(foo.clone());
-    let barlist_eval = eval(&mut env, List(vec!(cons.clone(), qfoo.clone(), qbar_list.clone())));
+    let barlist_eval = interp.eval(List(vec!(cons.clone(), qfoo.clone(), qbar_list.clone())));
     let foobar_list = make_2list(foo.clone(), bar.clone());
     assert!( barlist_eval.is_ok() && barlist_eval.unwrap().eq(&foobar_list) );
 }
 
 #[test]
 fn test_eval_cond() {
-    let mut env = Env::new();
+    let mut interp = Interpreter::new();
 
     let foo = make_atom("foo");
     let bar = make_atom("bar");
@@ -209,13 +232,1238 @@ fn test_eval_cond() {
                       make_2list(qfoo.clone(), qbar.clone()), 
                       make_2list(qt.clone(), qbaz.clone())));
 
-    let eval_list = eval(&mut env, list);
+    let eval_list = interp.eval(list);
 
     assert!( eval_list.is_ok() && eval_list.unwrap().eq(&baz) );
 
 
     let no_t_list = make_2list(cond.clone(), 
                                make_2list(qfoo.clone(), qbar.clone()));
-    let eval_no_t_list = eval(&mut env, no_t_list);
+    let eval_no_t_list = interp.eval(no_t_list);
     assert!( eval_no_t_list.is_ok() && eval_no_t_list.unwrap().is_nil() );
 }
+
+#[test]
+fn test_eval_lambda() {
+    let mut interp = Interpreter::new();
+
+    assert!( interp.eval_str("(lambda (x) x)").unwrap().is_closure() );
+
+    let res = interp.eval_str("((lambda (x) (cons x '(b))) 'a)");
+    assert!( res.unwrap().eq(&read_one("(a b)")) );
+
+    // a lambda returned from another lambda remembers the outer argument
+    let res = interp.eval_str("(((lambda (x) (lambda (y) (cons x (cons y '())))) 'a) 'b)");
+    assert!( res.unwrap().eq(&read_one("(a b)")) );
+
+    let res = interp.eval_str("((lambda (x y) x) 'a)");
+    assert!( res.is_err() );
+}
+
+#[test]
+fn test_eval_closure_lexical_scope() {
+    let mut interp = Interpreter::new();
+
+    // `call` binds its own `x`, which must not leak into the closure
+    let res = interp.eval_str("
+        (defun make (x) (lambda (y) (cons x (cons y '()))))
+        (defun call (x f) (f x))
+        (call 'b (make 'a))");
+    assert!( res.unwrap().eq(&read_one("(a b)")) );
+
+    // free variables are not resolved in the caller's environment
+    let res = interp.eval_str("
+        (defun free () z)
+        ((lambda (z) (free)) 'a)");
+    assert!( res.is_err() );
+}
+
+#[test]
+fn test_eval_label() {
+    let mut interp = Interpreter::new();
+
+    let res = interp.eval_str("
+        ((label last (lambda (x) (cond ((eq (rest x) '()) (first x))
+                                       ('t (last (rest x))))))
+         '(a b c))");
+    assert!( res.unwrap().eq(&make_atom("c")) );
+}
+
+#[test]
+fn test_eval_defun() {
+    let mut interp = Interpreter::new();
+
+    // functions can call functions that are defined after them
+    let res = interp.eval_str("
+        (defun foo (x) (bar x))
+        (defun bar (x) (cons x '(a b)))
+        (foo 'sup)");
+    assert!( res.unwrap().eq(&read_one("(sup a b)")) );
+}
+
+#[test]
+fn test_eval_let() {
+    let mut interp = Interpreter::new();
+
+    check(&mut interp, "(let ((x 'a) (y 'b)) (cons x (cons y '())))", "(a b)");
+    check(&mut interp, "(let () 'a)", "a");
+    // the values of a `let` are evaluated outside it, those of a `let*` see
+    // the bindings before them
+    check(&mut interp, "(let ((x 1)) (let ((x 2) (y x)) y))", "1");
+    check(&mut interp, "(let ((x 1)) (let* ((x 2) (y x)) y))", "2");
+    check(&mut interp, "(let* ((x 1) (x (+ x 1))) x)", "2");
+
+    // bindings are lexically scoped
+    let res = interp.eval_str("
+        (defun free () z)
+        (let ((z 'a)) (free))");
+    assert!( matches!(res, Err(Error::UnboundSymbol(ref s)) if s == "z") );
+    check(&mut interp, "((let ((x 'a)) (lambda (y) (cons x (cons y '())))) 'b)", "(a b)");
+
+    // the functions of a `letrec` can call each other
+    check(&mut interp, "
+        (letrec ((even? (lambda (n) (cond ((= n 0) 't) ('t (odd? (- n 1))))))
+                 (odd? (lambda (n) (cond ((= n 0) '()) ('t (even? (- n 1)))))))
+          (even? 5000))", "t");
+
+    // but can't use each other's values before they have been evaluated,
+    // even if an outer binding has the same name
+    for src in &["(letrec ((a b) (b 1)) a)", "(let ((b 2)) (letrec ((a b) (b 1)) a))",
+                 "(letrec ((f (lambda () g)) (g (f))) g)"] {
+        match interp.eval_str(src) {
+            Err(Error::Uninitialized(ref s)) => assert!( s == "b" || s == "g" ),
+            res => panic!("unexpected result for {}: {:?}", src, res),
+        }
+    }
+    check(&mut interp, "(letrec ((a 1) (b a)) b)", "1");
+
+    // a named `let` loops, in constant space
+    check(&mut interp, "
+        (let loop ((n 10000) (acc 0))
+          (cond ((= n 0) acc)
+                ('t (loop (- n 1) (+ acc 1)))))", "10000");
+
+    check_malformed(&mut interp, &["(let ((x)) x)", "(let (x) x)", "(let x)", "(let* ((1 2)) 3)", "(letrec x 1)",
+                                   "(let ((x 1) (x 2)) x)", "(letrec ((f 1) (g 2) (f 3)) f)"]);
+}
+
+#[test]
+fn test_eval_bodies() {
+    let mut interp = Interpreter::new();
+
+    check(&mut interp, "(define n 0) (begin (set! n 1) (set! n (+ n 1)) (cons n '()))", "(2)");
+    check(&mut interp, "(begin)", "");
+
+    // bodies are evaluated in order, and the value of the last expression is
+    // the value of the body
+    check(&mut interp, "((lambda (x) (set! n x) (+ n 1)) 5)", "6");
+    check(&mut interp, "n", "5");
+    check(&mut interp, "((label f (lambda (x) (set! n x) x)) 6)", "6");
+    check(&mut interp, "(cond ('() 1) ('t (set! n 7) (+ n 1)))", "8");
+    check(&mut interp, "(let ((x 1)) (set! n x) (+ n 1))", "2");
+    check(&mut interp, "(let loop ((i 3)) (set! n i) (cond ((= i 0) n) ('t (loop (- i 1)))))", "0");
+
+    // internal defines
+    check(&mut interp, "
+        (defun sum-squares (x y)
+          (define (square z) (* z z))
+          (define x2 (square x))
+          (+ x2 (square y)))
+        (sum-squares 3 4)", "25");
+    assert!( interp.get("square").is_none() );
+
+    // the last expression is in tail position
+    check(&mut interp, "
+        (defun count-down (i)
+          (set! n i)
+          (cond ((= i 0) 'done)
+                ('t (begin (set! n i) (count-down (- i 1))))))
+        (count-down 5000)", "done");
+
+    match interp.eval_str("(defun g (x))") {
+        Err(Error::ArityMismatch { expected: Arity::AtLeast(3), actual: 2, .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+    check_malformed(&mut interp, &["(lambda (x))", "(cond ('t))", "(define x 1 2)"]);
+}
+
+#[test]
+fn test_eval_conditionals() {
+    let mut interp = Interpreter::new();
+
+    // as for cond, only t is true
+    check(&mut interp, "(if (eq 'a 'a) 'yes 'no)", "yes");
+    check(&mut interp, "(if '() 'yes 'no)", "no");
+    check(&mut interp, "(if 'a 'yes 'no)", "no");
+    check(&mut interp, "(if '() 'yes)", "");
+    check(&mut interp, "(if 't 'yes (undefined))", "yes");
+
+    check(&mut interp, "(define n 0) (when 't (set! n 1) (+ n 1))", "2");
+    check(&mut interp, "(when '() (set! n 5))", "");
+    check(&mut interp, "(unless '() (set! n 3) n)", "3");
+    check(&mut interp, "(unless 't (set! n 5))", "");
+    check(&mut interp, "n", "3");
+
+    // and and or stop at the first argument that decides the result
+    check(&mut interp, "(and)", "t");
+    check(&mut interp, "(or)", "()");
+    check(&mut interp, "(and 't 't 'a)", "a");
+    check(&mut interp, "(and 't '() (undefined))", "()");
+    check(&mut interp, "(or '() 't (undefined))", "t");
+    check(&mut interp, "(or '() '() 'a)", "a");
+    interp.eval_str("
+        (defun nil? (x) (eq x '()))
+        (defun not (x) (cond (x '()) ('t 't)))").unwrap();
+    check(&mut interp, "(define x '()) (and (not (nil? x)) (first x))", "()");
+    check(&mut interp, "(define x '(a)) (and (not (nil? x)) (first x))", "a");
+
+    check(&mut interp, "(case (+ 1 2) ((1 2) 'low) ((3 4) 'mid) (else 'high))", "mid");
+    check(&mut interp, "(case 'x ((a) 1) (else (set! n 4) n))", "4");
+    check(&mut interp, "(case 'x ((a) 1))", "");
+    check(&mut interp, "(case '(a b) (((a b)) 'pair))", "pair");
+
+    // the branches are in tail position
+    check(&mut interp, "
+        (defun loop (i)
+          (if (= i 0)
+              (and 't (or '() (case i (else (when 't (unless '() 'done))))))
+              (loop (- i 1))))
+        (loop 5000)", "done");
+
+    check_malformed(&mut interp, &["(if 't)", "(if 't 1 2 3)", "(when 't)", "(unless)",
+                                   "(case)", "(case 1 (1 2))", "(case 1 ((1)))"]);
+
+    // else has to be the last clause, as any after it could never be chosen
+    match interp.eval_str("(case 'a (else 1) ((a) 2))") {
+        Err(Error::BadForm { ref form, ref expr, .. }) => {
+            assert_eq!(form, "case");
+            assert!( expr.eq(&read_one("(else 1)")) );
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    check(&mut interp, "(case 'b ((a) 1) (else 2))", "2");
+}
+
+#[test]
+fn test_env_frames() {
+    let env = Env::new();
+    env.insert("foo".to_string(), make_atom("a"));
+
+    let child = env.extend();
+    assert!( child.find_copy("foo").unwrap().eq(&make_atom("a")) );
+
+    // shadowing in the child frame leaves the parent alone
+    child.insert("foo".to_string(), make_atom("b"));
+    assert!( child.find_copy("foo").unwrap().eq(&make_atom("b")) );
+    assert!( env.find_copy("foo").unwrap().eq(&make_atom("a")) );
+
+    // global definitions made from a child are seen by every frame
+    child.extend().insert_global("bar".to_string(), make_atom("c"));
+    assert!( env.find_copy("bar").unwrap().eq(&make_atom("c")) );
+    assert!( child.find_copy("bar").unwrap().eq(&make_atom("c")) );
+
+    // setting changes the nearest binding, and only if there is one
+    assert!( child.extend().set("foo", make_atom("d")) );
+    assert!( child.find_copy("foo").unwrap().eq(&make_atom("d")) );
+    assert!( env.find_copy("foo").unwrap().eq(&make_atom("a")) );
+    assert!( !child.set("baz", make_atom("e")) );
+    assert!( child.find_copy("baz").is_none() );
+}
+
+#[test]
+fn test_eval_define() {
+    let mut interp = Interpreter::new();
+
+    check(&mut interp, "(define x (+ 1 2))", "");
+    check(&mut interp, "x", "3");
+    check(&mut interp, "(define (twice f y) (f (f y))) (twice (lambda (n) (* n n)) x)", "81");
+    assert_eq!(format!("{}", interp.get("twice").unwrap()), "#<function twice>");
+
+    // defines inside a body are local to it
+    check(&mut interp, "(defun f () (define local 'a)) (f)", "");
+    assert!( interp.get("local").is_none() );
+    check(&mut interp, "(let ((x 1)) (define x 2))", "");
+    check(&mut interp, "x", "3");
+
+    // set! changes the nearest binding, including one a closure captured
+    check(&mut interp, "(set! x 4)", "4");
+    check(&mut interp, "x", "4");
+    check(&mut interp, "(let ((x 1)) (set! x 5))", "5");
+    check(&mut interp, "x", "4");
+    interp.eval_str("
+        (define counter
+          (let ((n 0))
+            (lambda () (set! n (+ n 1)))))").unwrap();
+    check(&mut interp, "(counter)", "1");
+    check(&mut interp, "(counter)", "2");
+
+    match interp.eval_str("(set! undefined 1)") {
+        Err(Error::UnboundSymbol(ref s)) => assert_eq!(s, "undefined"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!( interp.get("undefined").is_none() );
+    check_malformed(&mut interp, &["(define x)", "(define 1 2)", "(define () 2)", "(set! (x) 1)"]);
+}
+
+#[test]
+fn test_env_closure_cycles() {
+    let mut interp = Interpreter::new();
+
+    // a closure bound in the frame it was made in doesn't keep that frame
+    // alive, but still sees it when it is looked up
+    let env = interp.env().extend();
+    env.insert("x".to_string(), make_atom("a"));
+    let f = eval::eval(&mut interp, &env, read_one("(lambda () x)")).unwrap();
+    env.insert("f".to_string(), f.clone());
+    assert_eq!(env.strong_count(), 2);
+    drop(f);
+    assert_eq!(env.strong_count(), 1);
+    let res = eval::eval(&mut interp, &env, read_one("(cons (f) (cons (eq f f) '()))"));
+    assert!( res.unwrap().eq(&read_one("(a t)")) );
+    assert_eq!(env.strong_count(), 1);
+
+    // so the frames of letrecs are freed once they have been evaluated,
+    // and stop holding on to the global frame
+    interp.eval_str("
+        (defun use (n)
+          (letrec ((f (lambda (x) (g x)))
+                   (g (lambda (x) x)))
+            (f n)))").unwrap();
+    let count = interp.env().strong_count();
+    assert_eq!(format!("{}", interp.eval_str("(use 1) (use 2)").unwrap()), "2");
+    assert_eq!(interp.env().strong_count(), count);
+
+    // as are the frames of bodies that define functions of their own
+    interp.eval_str("
+        (defun sum-squares (a b)
+          (define (sq x) (* x x))
+          (+ (sq a) (sq b)))").unwrap();
+    let count = interp.env().strong_count();
+    assert_eq!(format!("{}", interp.eval_str("(sum-squares 1 2) (sum-squares 3 4)").unwrap()), "25");
+    assert_eq!(interp.env().strong_count(), count);
+
+    // a closure bound in a frame outside the one it was made in keeps that
+    // frame alive, but the two are freed when nothing else refers to them
+    interp.eval_str("
+        (defun use-inner (n)
+          (letrec ((f (let ((k 1)) (lambda (n) (if (= n 0) k (f (- n 1)))))))
+            (f n)))
+        (defun use-set (n)
+          (define g '())
+          (let ((k n)) (set! g (lambda () k)))
+          (g))
+        (defun use-define (n)
+          (define h (let ((k n)) (lambda () k)))
+          (h))").unwrap();
+    collect_cycles();
+    let count = interp.env().strong_count();
+    for src in &["(use-inner 3)", "(use-set 1)", "(use-define 1)"] {
+        assert_eq!(format!("{}", interp.eval_str(src).unwrap()), "1");
+        assert!( interp.env().strong_count() > count, "evaluating {}", src );
+        assert!( collect_cycles() > 0 );
+        assert_eq!(interp.env().strong_count(), count, "evaluating {}", src);
+    }
+
+    // which happens by itself as more frames are made
+    interp.eval_str("
+        (defun repeat (n)
+          (when (> n 0) (use-inner 1) (use-set 1) (use-define 1) (repeat (- n 1))))
+        (repeat 10000)").unwrap();
+    assert!( interp.env().strong_count() < count + 5000 );
+
+    // and a closure that outlives its letrec keeps the frame it needs
+    interp.eval_str("
+        (defun make ()
+          (letrec ((f (lambda (n) (cond ((= n 0) 'done) ('t (f (- n 1)))))))
+            f))").unwrap();
+    assert!( interp.eval_str("((make) 3)").unwrap().eq(&make_atom("done")) );
+}
+
+#[test]
+fn test_eval_errors() {
+    let mut interp = Interpreter::new();
+
+    match interp.eval_str("foo") {
+        Err(Error::UnboundSymbol(ref s)) => assert_eq!(s, "foo"),
+        _ => panic!("expected an unbound symbol error"),
+    }
+
+    match interp.eval_str("(defun f (x y) x) (f 'a)") {
+        Err(Error::ArityMismatch { ref name, expected: Arity::Exactly(2), actual: 1, ref expr }) => {
+            assert_eq!(name, "f");
+            assert!( expr.eq(&read_one("(f 'a)")) );
+        },
+        _ => panic!("expected an arity mismatch"),
+    }
+
+    match interp.eval_str("(first 'foo)") {
+        Err(Error::WrongType { ref builtin, ref expr, .. }) => {
+            assert_eq!(builtin, "first");
+            assert!( expr.eq(&make_atom("foo")) );
+        },
+        _ => panic!("expected a type error"),
+    }
+
+    match interp.eval_str("(cond foo)") {
+        Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "cond"),
+        _ => panic!("expected a malformed cond"),
+    }
+
+    match interp.eval_str("('foo 'bar)") {
+        Err(Error::NotAProcedure(ref expr)) => assert!( expr.eq(&make_atom("foo")) ),
+        _ => panic!("expected a non-procedure error"),
+    }
+
+    // a quoted lambda is a list, not a procedure
+    match interp.eval_str("('(lambda (x) x) 'a)") {
+        Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "lambda"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // rest parameters aren't supported, and a parameter can't be bound twice
+    let bad_params = [("(define (g . rest) rest)", "define"), ("(lambda (x x) x)", "lambda"),
+                      ("(label f (lambda (. x) x))", "label"), ("(defun f (x y x) y)", "defun"),
+                      ("(defmacro m (. x) x)", "defmacro"), ("(let loop ((x 1) (x 2)) x)", "let")];
+    for &(src, name) in &bad_params {
+        match interp.eval_str(src) {
+            Err(Error::BadForm { ref form, .. }) => assert_eq!(form, name),
+            res => panic!("unexpected result for {}: {:?}", src, res),
+        }
+    }
+    assert_eq!(format!("{}", interp.eval_str("(define (g x y) y) (g 1 2)").unwrap()), "2");
+
+    let msg = format!("{}", interp.eval_str("(first 'foo)").unwrap_err());
+    assert_eq!(msg, "`first` expects a non-empty list, but got: foo");
+}
+
+#[test]
+fn test_read_error_positions() {
+    match read("(foo\n  bar))") {
+        Err(Error::Read { line: 2, column: 7, .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    match read("(foo") {
+        Err(Error::Incomplete { line: 1, column: 1, .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn test_read_atoms_and_lists() {
+    assert!( read("").unwrap().is_empty() );
+    assert!( read(" \t\r\n ").unwrap().is_empty() );
+    assert!( read("foo").unwrap().eq(&vec!(make_atom("foo"))) );
+    assert!( read("  foo\r\n\tbar ").unwrap().eq(&vec!(make_atom("foo"), make_atom("bar"))) );
+
+    // symbols can contain most punctuation, and any unicode
+    let exprs = read("exact->inexact nil? + λ <= *x*").unwrap();
+    let names: Vec<String> = exprs.into_iter().map(|e| e.unwrap_atom()).collect();
+    assert_eq!(names, vec!("exact->inexact", "nil?", "+", "λ", "<=", "*x*"));
+
+    assert!( read("()").unwrap().eq(&vec!(make_nil())) );
+    assert!( read("[]{}").unwrap().eq(&vec!(make_nil(), make_nil())) );
+    assert!( read("(a (b (c)) ())").unwrap()[0].eq(
+        &List(vec!(make_atom("a"),
+                   make_2list(make_atom("b"), List(vec!(make_atom("c")))),
+                   make_nil()))) );
+
+    // delimiters separate atoms without any whitespace
+    assert_eq!(format!("{}", read_one("(a(b)c'd)")), "(a (b) c (quote d))");
+    assert_eq!(read("foo(bar)baz").unwrap().len(), 3);
+
+    // quotes nest, and quote whole lists
+    assert!( read_one("''a").eq(&quote_expr(quote_expr(make_atom("a")))) );
+    assert!( read_one("'(a b)").eq(&quote_expr(make_2list(make_atom("a"), make_atom("b")))) );
+    assert!( read_one("' a").eq(&quote_expr(make_atom("a"))) );
+
+    // the result prints as it was read
+    let src = "(defun f (x) (cond ((eq x 1) \"one\") ((quote t) (cons x (quote (2 3/4 -5.5))))))";
+    assert_eq!(format!("{}", read_one(src)), src);
+}
+
+#[test]
+fn test_read_incomplete() {
+    let check = |src: &str, reason: &str, line: usize, column: usize| {
+        match read(src) {
+            Err(Error::Incomplete { reason: ref r, line: l, column: c }) => {
+                assert_eq!((&r[..], l, c), (reason, line, column), "reading {:?}", src);
+            },
+            res => panic!("unexpected result reading {:?}: {:?}", src, res),
+        }
+        assert!( needs_more_input(src) );
+    };
+
+    check("(foo", "Unclosed list opened", 1, 1);
+    check("(foo\n  (bar", "Unclosed list opened", 2, 3);
+    check("(foo (bar) ; (baz)\n", "Unclosed list opened", 1, 1);
+    check("foo (bar #;(baz)", "Unclosed list opened", 1, 5);
+    check("(foo \"bar", "Unterminated string literal", 1, 6);
+    check("\"bar\\", "Unterminated string literal", 1, 1);
+    check("(foo #| bar", "Unterminated block comment", 1, 6);
+    check("(foo) '", "Unexpected end of input", 1, 8);
+    check("foo #;", "Unexpected end of input", 1, 7);
+
+    assert_eq!(format!("{}", read("(defun f (x)\n  (g x)").unwrap_err()),
+               "Unclosed list opened at line 1, column 1");
+
+    // errors before the end of the input are not incomplete input
+    for src in &["(foo))", "(foo]", ")", "(foo] (bar", "\"\\q (foo"] {
+        match read(src) {
+            Err(Error::Read { .. }) => assert!( !needs_more_input(src) ),
+            res => panic!("unexpected result reading {:?}: {:?}", src, res),
+        }
+    }
+}
+
+#[test]
+fn test_read_errors() {
+    match read("foo\n  ]") {
+        Err(Error::Read { ref reason, line: 2, column: 3 }) => assert_eq!(reason, "Unexpected list end token `]`"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match read("(a \"\\u{zz}\")") {
+        Err(Error::Read { line: 1, column: 5, .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    match read("(a\n -1/00)") {
+        Err(Error::Read { ref reason, line: 2, column: 2 }) => assert_eq!(reason, "Zero denominator in `-1/00`"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // the first error is reported, wherever it is
+    match read("(a) b) (c") {
+        Err(Error::Read { line: 1, column: 6, .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn test_token_spans() {
+    let src = "(foo\n  'bär)";
+    let toks: Vec<_> = tokenize(src).unwrap().collect();
+    let texts: Vec<&str> = toks.iter().map(|t| t.text.as_ref()).collect();
+    assert_eq!(texts, vec!("(", "foo", "'", "bär", ")"));
+
+    let bar = toks[3].span;
+    assert_eq!((bar.start.line, bar.start.column, bar.start.offset), (2, 4, 8));
+    assert_eq!((bar.end.line, bar.end.column, bar.end.offset), (2, 7, 12));
+    assert_eq!(bar.source(src), "bär");
+}
+
+#[test]
+fn test_read_delimiters() {
+    let exprs = read("(cond [(eq x y) 'a] {'t 'b})").unwrap();
+    assert_eq!(format!("{}", exprs[0]), "(cond ((eq x y) (quote a)) ((quote t) (quote b)))");
+
+    // the error is reported at the closing delimiter, and says where the
+    // list it should have closed was opened
+    match read("(cond [(eq x y) 'a)\n      ['t 'b])") {
+        Err(Error::Read { ref reason, line: 1, column: 19 }) => {
+            assert_eq!(reason, "Mismatched `)`: the `[` at line 1, column 7 must be closed by `]`");
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match read("{foo\n (bar))") {
+        Err(Error::Read { ref reason, line: 2, column: 7 }) => assert!( reason.contains("line 1, column 1") ),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!( read("(foo]").is_err() );
+    assert!( read("[foo}").is_err() );
+
+    // delimiters in strings are just text
+    let exprs = read(r##"(foo "(" "]" "'" "#;")"##).unwrap();
+    assert_eq!(exprs[0].get_ref_list().len(), 5);
+}
+
+#[test]
+fn test_read_spans() {
+    let src = "(cons 'a\n      (car x))";
+    let (expr, spans) = read_spanned(src).unwrap().remove(0);
+
+    assert_eq!(spans.span.source(src), src);
+    assert_eq!(spans.children.len(), 3);
+    assert_eq!(spans.children[1].span.source(src), "'a");
+    assert_eq!(spans.children[1].children[1].span.source(src), "a");
+
+    assert_eq!(expr.get_ref_list()[2].get_ref_list()[0], make_atom("car"));
+    let car = spans.at(&[2, 0]).unwrap();
+    assert_eq!((car.start.line, car.start.column), (2, 8));
+    assert_eq!(car.show(src), "      (car x))\n       ^^^");
+
+    assert_eq!( spans.at(&[]), Some(spans.span) );
+    assert!( spans.at(&[2, 2]).is_none() );
+}
+
+#[test]
+fn test_eval_traced() {
+    let mut interp = Interpreter::new();
+    assert!( interp.eval_traced(&read_one("(+ 1 2)")).is_ok() );
+    let expr = read_one("(if (= 1 2) (first 'a) (first 'a))");
+    assert!( interp.eval_traced(&expr).is_err() );
+    assert_eq!(interp.error_path(&expr), vec!(3));
+
+    let mut failure = |src: &str| {
+        let (expr, spans) = read_spanned(src).unwrap().remove(0);
+        assert!( interp.eval_traced(&expr).is_err() );
+        spans.at(&interp.error_path(&expr)).unwrap().source(src).to_string()
+    };
+
+    // the failing subexpression, not the first one that looks like it
+    assert_eq!(failure("(cons 'foo foo)"), "foo");
+    assert_eq!(failure("(if (= 1 2) (first 'a) (first 'a))"), "(first 'a)");
+
+    assert_eq!(failure("(let ((x 1)) (+ x 1) (+ x y) (+ x 1))"), "y");
+    assert_eq!(failure("(cond ((= 1 1) (first 1)))"), "(first 1)");
+    assert_eq!(failure("(cons 1 (when (= 1 1) (quote)))"), "(quote)");
+
+    // errors in the bodies of procedures are reported at the call
+    assert_eq!(failure("(cons 1 ((lambda (x) (first x)) 1))"), "((lambda (x) (first x)) 1)");
+    // even when the call is the whole form
+    assert_eq!(failure("((lambda () (first 1)))"), "((lambda () (first 1)))");
+}
+
+#[test]
+fn test_read_comments() {
+    let foo_bar = vec!(make_atom("foo"), make_atom("bar"));
+
+    assert!( read("foo ; a comment (\nbar ;").unwrap().eq(&foo_bar) );
+    assert!( read("foo;comment\nbar").unwrap().eq(&foo_bar) );
+
+    // block comments nest, and can sit in the middle of a list
+    assert!( read("#| a #| nested |# ( comment |# foo bar").unwrap().eq(&foo_bar) );
+    assert!( read("(foo #| x |# bar)").unwrap().eq(&vec!(List(foo_bar.clone()))) );
+    assert!( read("foo #| #| |# bar").is_err() );
+
+    // datum comments skip exactly one expression, wherever it is
+    assert!( read("#;(defun f (x) x) foo bar").unwrap().eq(&foo_bar) );
+    assert!( read("foo #; #;baz quux bar").unwrap().eq(&foo_bar) );
+    assert!( read("(foo #;(baz) bar #;quux)").unwrap().eq(&vec!(List(foo_bar.clone()))) );
+    assert!( read("'#;baz foo").unwrap().eq(&vec!(quote_expr(make_atom("foo")))) );
+    assert!( read("foo #;").is_err() );
+}
+
+#[test]
+fn test_read_strings() {
+    let exprs = read(r#"("hello world" "(not a list)" foo"bar")"#).unwrap();
+    let expected = List(vec!(Str("hello world".to_string()),
+                             Str("(not a list)".to_string()),
+                             make_atom("foo"),
+                             Str("bar".to_string())));
+    assert!( exprs[0].eq(&expected) );
+
+    let exprs = read(r#""a\n\t\"b\"\\ \u{3bb}\u{1F600}""#).unwrap();
+    assert!( exprs[0].eq(&Str("a\n\t\"b\"\\ \u{3bb}\u{1F600}".to_string())) );
+
+    // a comment marker inside a string is just text
+    assert!( read("\"; #| x\"").unwrap()[0].eq(&Str("; #| x".to_string())) );
+
+    assert!( read(r#""unterminated"#).is_err() );
+    assert!( read(r#""bad \q escape""#).is_err() );
+    assert!( read(r#""\u{110000}""#).is_err() );
+    assert!( read(r#""\u41""#).is_err() );
+}
+
+#[test]
+fn test_print_strings() {
+    let s = Str("say \"hi\"\n\\".to_string());
+    assert_eq!(format!("{}", s), r#""say \"hi\"\n\\""#);
+
+    // printing a string and reading it back gives the same string
+    let printed = format!("{}", List(vec!(s.clone(), Str("\u{1}".to_string()))));
+    assert!( read(&printed).unwrap()[0].eq(&List(vec!(s, Str("\u{1}".to_string())))) );
+}
+
+#[test]
+fn test_eval_strings() {
+    let mut interp = Interpreter::new();
+
+    assert!( interp.eval_str("\"foo\"").unwrap().eq(&Str("foo".to_string())) );
+    assert!( interp.eval_str("(atom \"foo\")").unwrap().eq(&make_atom("t")) );
+    assert!( interp.eval_str("(eq \"foo\" \"foo\")").unwrap().eq(&make_atom("t")) );
+    assert!( interp.eval_str("(eq \"foo\" 'foo)").unwrap().eq(&make_nil()) );
+}
+
+#[test]
+fn test_read_numbers() {
+    let exprs = read("(42 -7 +3 1.5 -.5 6.02e23 99999999999999999999 1+ - ... e10 inf)").unwrap();
+    let big = "99999999999999999999".parse().unwrap();
+    let expected = List(vec!(Num(Number::Int(42)), Num(Number::Int(-7)), Num(Number::Int(3)),
+                             Num(Number::Float(1.5)), Num(Number::Float(-0.5)), Num(Number::Float(6.02e23)),
+                             Num(Number::Big(big)),
+                             make_atom("1+"), make_atom("-"), make_atom("..."), make_atom("e10"), make_atom("inf")));
+    assert!( exprs[0].eq(&expected) );
+
+    // floats print so that they read back as floats
+    assert_eq!(format!("{}", read_one("(2.0 -0.25 1e100)")), "(2.0 -0.25 1e100)");
+
+    // floats too large to represent would read as infinity, which can't be
+    // printed so that it reads back
+    assert!( Number::parse("1e400").is_none() && Number::parse("-1e400").is_none() );
+    match read("(a\n -1e400)") {
+        Err(Error::Read { ref reason, line: 2, column: 2 }) => assert_eq!(reason, "Float out of range in `-1e400`"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!( read_one("1e-400").eq(&Num(Number::Float(0.0))) );
+}
+
+#[test]
+fn test_eval_arithmetic() {
+    let mut interp = Interpreter::new();
+
+    check(&mut interp, "(+)", "0");
+    check(&mut interp, "(+ 1 2 3)", "6");
+    check(&mut interp, "(- 5)", "-5");
+    check(&mut interp, "(- 10 1 2)", "7");
+    check(&mut interp, "(* 2 3 4)", "24");
+    check(&mut interp, "(/ 12 4)", "3");
+    check(&mut interp, "(/ 1 4)", "1/4");
+    check(&mut interp, "(/ 1.0 4)", "0.25");
+    check(&mut interp, "(+ 1 0.5)", "1.5");
+    check(&mut interp, "(quotient -7 2)", "-3");
+    check(&mut interp, "(mod -7 2)", "1");
+    check(&mut interp, "(mod 7 -2)", "-1");
+
+    // integers are promoted to big integers on overflow, and back again
+    check(&mut interp, "(+ 9223372036854775807 1)", "9223372036854775808");
+    check(&mut interp, "(* 4294967296 4294967296 4294967296)", "79228162514264337593543950336");
+    check(&mut interp, "(- (+ 9223372036854775807 1) 1)", "9223372036854775807");
+    check(&mut interp, "(quotient -9223372036854775808 -1)", "9223372036854775808");
+    assert!( interp.eval_str("(- (+ 9223372036854775807 1) 1)").unwrap()
+             .eq(&Num(Number::Int(9223372036854775807))) );
+
+    check(&mut interp, "(< 1 2 3)", "t");
+    check(&mut interp, "(< 1 3 2)", "()");
+    check(&mut interp, "(>= 3 3 1.5)", "t");
+    check(&mut interp, "(= 2 2.0)", "t");
+    check(&mut interp, "(> 100000000000000000000 1e10)", "t");
+    check(&mut interp, "(<= 2 1)", "()");
+
+    check(&mut interp, "(defun fact (n) (cond ((= n 0) 1) ('t (* n (fact (- n 1)))))) (fact 25)",
+          "15511210043330985984000000");
+}
+
+#[test]
+fn test_eval_arithmetic_errors() {
+    let mut interp = Interpreter::new();
+
+    match interp.eval_str("(/ 1 0)") {
+        Err(Error::DivisionByZero(_)) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("(mod 1 0)") {
+        Err(Error::DivisionByZero(_)) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("(+ 1 'a)") {
+        Err(Error::WrongType { ref expr, .. }) => assert!( expr.eq(&make_atom("a")) ),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("(quotient 1.5 1)") {
+        Err(Error::WrongType { .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("(<)") {
+        Err(Error::ArityMismatch { expected: Arity::AtLeast(1), actual: 0, .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn test_rationals() {
+    let mut interp = Interpreter::new();
+
+    // rationals are read in lowest terms, and become integers when they can
+    assert_eq!(format!("{}", read_one("(1/3 -6/4 +4/2 0/5 1/-2 1.5/2 a/b)")),
+               "(1/3 -3/2 2 0 1/-2 1.5/2 a/b)");
+    assert!( read_one("4/2").eq(&Num(Number::Int(2))) );
+
+    check(&mut interp, "(/ 1 3)", "1/3");
+    check(&mut interp, "(/ 6 -4)", "-3/2");
+    check(&mut interp, "(/ 3)", "1/3");
+    check(&mut interp, "(+ 1/3 2/3)", "1");
+    check(&mut interp, "(* 2/3 3/4)", "1/2");
+    check(&mut interp, "(- 1/2 1)", "-1/2");
+    check(&mut interp, "(/ 1/2 1/4)", "2");
+    check(&mut interp, "(+ 1/2 0.25)", "0.75");
+    check(&mut interp, "(/ 1 3 9223372036854775807)", "1/27670116110564327421");
+
+    check(&mut interp, "(< 1/3 0.34 1/2)", "t");
+    check(&mut interp, "(= 1/2 2/4 0.5)", "t");
+    check(&mut interp, "(> 1/3 1/3)", "()");
+
+    check(&mut interp, "(numerator 6/4)", "3");
+    check(&mut interp, "(denominator 6/4)", "2");
+    check(&mut interp, "(numerator -5)", "-5");
+    check(&mut interp, "(denominator -5)", "1");
+    check(&mut interp, "(exact->inexact 1/4)", "0.25");
+    check(&mut interp, "(exact->inexact 3)", "3.0");
+
+    assert!( interp.eval_str("(numerator 0.5)").is_err() );
+    assert!( interp.eval_str("(/ 1/2 0)").is_err() );
+    assert!( interp.eval_str("(mod 1/2 2)").is_err() );
+}
+
+fn native_pair(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    Ok( make_2list(args[0].clone(), args[1].clone()) )
+}
+
+fn native_count(_: &mut Interpreter, args: &[Expr]) -> EvalResult {
+    Ok( Num(Number::Int(args.len() as i64)) )
+}
+
+#[test]
+fn test_native_registry() {
+    let mut interp = Interpreter::new();
+    interp.register("pair", Arity::Exactly(2), native_pair);
+    interp.register("count", Arity::AtLeast(1), native_count);
+
+    // natives get the values of their arguments
+    let res = interp.eval_str("(pair 'a (first '(b c)))");
+    assert!( res.unwrap().eq(&read_one("(a b)")) );
+    assert_eq!(format!("{}", interp.eval_str("(count 'a 'b 'c)").unwrap()), "3");
+
+    match interp.eval_str("(count)") {
+        Err(Error::ArityMismatch { ref name, expected: Arity::AtLeast(1), actual: 0, .. }) => {
+            assert_eq!(name, "count");
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("(first '(a) '(b))") {
+        Err(Error::ArityMismatch { ref name, expected: Arity::Exactly(1), actual: 2, .. }) => {
+            assert_eq!(name, "first");
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // natives are first-class values
+    assert_eq!(format!("{}", interp.eval_str("cons").unwrap()), "#<builtin cons>");
+    let res = interp.eval_str("((lambda (f) (f 'a '(b))) cons)");
+    assert!( res.unwrap().eq(&read_one("(a b)")) );
+    assert!( interp.eval_str("(atom pair)").unwrap().eq(&make_atom("t")) );
+
+    // and like any other binding, they can be redefined
+    interp.eval_str("(defun first (x) 'mine)").unwrap();
+    assert!( interp.eval_str("(first '(a))").unwrap().eq(&make_atom("mine")) );
+}
+
+#[test]
+fn test_interpreter_api() {
+    let mut interp = Interpreter::new();
+
+    interp.define("n", 41);
+    interp.define("greeting", "hello");
+    interp.define("flag", true);
+    interp.eval_str("(defun inc (x) (+ x 1))").unwrap();
+
+    assert_eq!(i64::try_from(interp.eval_str("(inc n)").unwrap()).unwrap(), 42);
+    assert_eq!(String::try_from(interp.get("greeting").unwrap()).unwrap(), "hello");
+    assert!( bool::try_from(interp.get("flag").unwrap()).unwrap() );
+    assert!( interp.get("inc").unwrap().is_closure() );
+    assert!( interp.get("nope").is_none() );
+
+    let list = interp.eval_str("(cons 1.5 '(2))").unwrap();
+    let items = Vec::<Expr>::try_from(list).unwrap();
+    assert_eq!(f64::try_from(items[0].clone()).unwrap(), 1.5);
+    assert_eq!(f64::try_from(items[1].clone()).unwrap(), 2.0);
+
+    match i64::try_from(make_atom("foo")) {
+        Err(Error::Conversion { to: "i64", .. }) => {},
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!( bool::try_from(make_atom("foo")).is_err() );
+
+    // an empty program has no value, and a bad one stops at the first error
+    assert!( interp.eval_str("").unwrap().is_nil() );
+    assert!( interp.eval_str("(").is_err() );
+    assert!( interp.eval_str("(defun a () 1) (undefined) (defun b () 2)").is_err() );
+    assert!( interp.get("a").is_some() && interp.get("b").is_none() );
+}
+
+#[test]
+fn test_eval_file() {
+    let mut interp = Interpreter::new();
+
+    let res = interp.eval_file("code/test_defun.rad");
+    assert!( res.unwrap().eq(&read_one("(sup a b)")) );
+    assert!( interp.get("foo").unwrap().is_closure() );
+
+    match interp.eval_file("code/does_not_exist.rad") {
+        Err(Error::Io { ref path, .. }) => assert_eq!(path, "code/does_not_exist.rad"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn test_tail_calls() {
+    let mut interp = Interpreter::new();
+
+    // far deeper than the Rust stack would allow if each call recursed
+    let res = interp.eval_str("
+        (defun count-down (n) (cond ((= n 0) 'done)
+                                    ('t (count-down (- n 1)))))
+        (count-down 50000)");
+    assert!( res.unwrap().eq(&make_atom("done")) );
+
+    // mutual recursion, through cond branches, with an accumulator
+    let res = interp.eval_str("
+        (defun even? (n) (cond ((= n 0) 't) ('t (odd? (- n 1)))))
+        (defun odd? (n) (cond ((= n 0) '()) ('t (even? (- n 1)))))
+        (defun sum (n acc) (cond ((= n 0) acc) ('t (sum (- n 1) (+ acc n)))))
+        (cons (even? 30001) (cons (sum 30000 0) '()))");
+    assert_eq!(format!("{}", res.unwrap()), "(() 450015000)");
+
+    // labels and anonymous lambdas are called in tail position too
+    let res = interp.eval_str("
+        ((label loop (lambda (n) (cond ((= n 0) 'done) ('t ((lambda (m) (loop m)) (- n 1))))))
+         30000)");
+    assert!( res.unwrap().eq(&make_atom("done")) );
+}
+
+#[test]
+fn test_recursion_limit() {
+    let mut interp = Interpreter::new();
+    interp.set_max_depth(50);
+    assert_eq!(interp.max_depth(), 50);
+
+    interp.eval_str("
+        (defun f (x) (cons x (f x)))
+        (defun g () (cons 'b (f 'a)))
+        (defun h () (f 'a))").unwrap();
+
+    match interp.eval_str("(g)") {
+        Err(Error::RecursionLimit { limit: 50, ref calls }) => {
+            // the innermost level fails while evaluating the argument to `f`
+            assert_eq!(calls.len(), 49);
+            assert_eq!(calls[0], "g");
+            assert!( calls[1..].iter().all(|c| c == "f") );
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(format!("{}", interp.eval_str("(g)").unwrap_err()),
+               "Maximum recursion depth (50) exceeded, in: g -> f (x48)");
+
+    // `h` calls `f` in tail position, so it has left the call chain
+    match interp.eval_str("(h)") {
+        Err(Error::RecursionLimit { ref calls, .. }) => assert!( calls.iter().all(|c| c == "f") ),
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // the interpreter is still usable, and its definitions are intact
+    assert_eq!(format!("{}", interp.eval_str("(+ 1 2)").unwrap()), "3");
+    assert!( interp.get("g").is_some() );
+
+    // recursion within the limit is fine, and tail calls don't count
+    let res = interp.eval_str("
+        (defun deep (n) (cond ((= n 0) 0) ('t (+ 1 (deep (- n 1))))))
+        (defun loop (n) (cond ((= n 0) 'done) ('t (loop (- n 1)))))
+        (cons (deep 40) (cons (loop 1000) '()))");
+    assert_eq!(format!("{}", res.unwrap()), "(40 done)");
+    assert!( interp.eval_str("(deep 60)").is_err() );
+}
+
+#[test]
+fn test_recursion_limit_default() {
+    // Whatever kind of build this is, recursing past the default limit on a
+    // thread with the default stack size is an error, not a stack overflow.
+    let mut interp = Interpreter::new();
+    interp.eval_str("(defun deep (n) (cond ((= n 0) 0) ('t (+ 1 (deep (- n 1))))))").unwrap();
+    match interp.eval_str("(deep 100000)") {
+        Err(Error::RecursionLimit { limit, ref calls }) => {
+            assert!( limit <= interp.max_depth() );
+            assert!( calls.iter().all(|c| c == "deep") );
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(format!("{}", interp.eval_str("(deep 10)").unwrap()), "10");
+
+    // nor does a higher limit let it overflow the stack
+    interp.set_max_depth(1_000_000);
+    assert!( matches!(interp.eval_str("(deep 100000)"), Err(Error::RecursionLimit { .. })) );
+}
+
+#[test]
+fn test_needs_more_input() {
+    assert!( !needs_more_input("") );
+    assert!( !needs_more_input("foo\n") );
+    assert!( !needs_more_input("(defun f (x)\n  x)\n") );
+
+    assert!( needs_more_input("(defun f (x)\n") );
+    assert!( needs_more_input("(cond [(eq x y) {first\n") );
+    assert!( needs_more_input("(foo \"a string (\n") );
+    assert!( !needs_more_input("(foo \"\\\"\")\n") );
+    assert!( needs_more_input("foo #| a comment\n") );
+
+    // delimiters in strings and comments don't count
+    assert!( !needs_more_input("(foo \"(((\")\n") );
+    assert!( !needs_more_input("(foo) ; (((\n") );
+    assert!( !needs_more_input("(foo #| ( |#)\n") );
+
+    // errors are complete input, for the reader to report
+    assert!( !needs_more_input("(foo))\n") );
+    assert!( !needs_more_input("(foo \"\\q\n") );
+}
+
+#[test]
+fn test_load() {
+    let mut interp = Interpreter::new();
+
+    let res = interp.eval_str("(load \"code/test_defun.rad\")");
+    assert!( res.unwrap().eq(&read_one("(sup a b)")) );
+    assert!( interp.get("foo").unwrap().is_closure() );
+
+    match interp.eval_str("(load 'foo)") {
+        Err(Error::WrongType { ref builtin, .. }) => assert_eq!(builtin, "load"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+fn unwrap_closure(expr: Expr) -> ::std::rc::Rc<Closure> {
+    match expr {
+        Expression::Closure(c) => c,
+        expr => panic!("not a closure: {}", expr),
+    }
+}
+
+#[test]
+fn test_closure_source() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(defun f (x y) (cons x (quote (y))))").unwrap();
+
+    let f = unwrap_closure(interp.get("f").unwrap());
+    assert_eq!(format!("{}", f.defun().unwrap()), "(defun f (x y) (cons x (quote (y))))");
+    assert_eq!(format!("{}", f.literal()), "(label f (lambda (x y) (cons x (quote (y)))))");
+
+    let g = unwrap_closure(interp.eval_str("(lambda () \"hi\")").unwrap());
+    assert!( g.defun().is_none() );
+    assert_eq!(format!("{}", g.literal()), "(lambda () \"hi\")");
+
+    // the source evaluates to an equivalent definition
+    let src = format!("{}", f.defun().unwrap());
+    interp.reset();
+    interp.eval_str(&src).unwrap();
+    assert_eq!(format!("{}", interp.eval_str("(f 1 2)").unwrap()), "(1 y)");
+
+    // every expression of a body is kept
+    interp.eval_str("(defun h (x) (set! x 1) x)").unwrap();
+    let h = unwrap_closure(interp.get("h").unwrap());
+    assert_eq!(format!("{}", h.defun().unwrap()), "(defun h (x) (set! x 1) x)");
+}
+
+#[test]
+fn test_reset() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(defun f (x) x)").unwrap();
+    interp.define("x", 1);
+    let env = interp.env().clone();
+
+    interp.reset();
+    assert!( interp.get("f").is_none() && interp.get("x").is_none() );
+    assert!( interp.get("cons").is_some() );
+    assert!( env.find_copy("f").is_none() );
+    assert_eq!(env.names().len(), interp.env().names().len());
+}
+
+#[test]
+fn test_quasiquote() {
+    assert_eq!(format!("{}", read_one("`(a ,b ,@c)")),
+               "(quasiquote (a (unquote b) (unquote-splicing c)))");
+    assert_eq!(format!("{}", read_one("(a,b ,@ c)")), "(a (unquote b) (unquote-splicing c))");
+
+    let mut interp = Interpreter::new();
+
+    check(&mut interp, "`x", "x");
+    check(&mut interp, "`()", "()");
+    check(&mut interp, "`(1 ,(+ 1 1) ,@(cons 3 '(4)) 5)", "(1 2 3 4 5)");
+    check(&mut interp, "`(a (b ,(+ 1 2)) [c ,'d])", "(a (b 3) (c d))");
+    check(&mut interp, "`(a ,@'() b)", "(a b)");
+    check(&mut interp, "`,(+ 1 2)", "3");
+
+    // templates are handy for building code
+    interp.eval_str("(defun adder (n) `(lambda (x) (+ x ,n)))").unwrap();
+    check(&mut interp, "(adder 5)", "(lambda (x) (+ x 5))");
+
+    // only the unquotes of the outermost quasiquote are evaluated
+    check(&mut interp, "`(a `(b ,(c ,(+ 1 2))))", "(a (quasiquote (b (unquote (c 3)))))");
+    check(&mut interp, "`(a `(b ,@(c ,@(cons 1 '(2)))))", "(a (quasiquote (b (unquote-splicing (c 1 2)))))");
+
+    match interp.eval_str(",x") {
+        Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "unquote"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("`,@'(a)") {
+        Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "unquote-splicing"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("`(a ,@'b)") {
+        Err(Error::BadForm { ref form, ref expr, .. }) => {
+            assert_eq!(form, "unquote-splicing");
+            assert!( expr.eq(&read_one(",@'b")) );
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!( interp.eval_str("`(a ,undefined)").is_err() );
+}
+
+#[test]
+fn test_macros() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("
+        (defmacro my-and (a b) `(cond (,a ,b) ('t '())))
+        (defmacro my-and-too (a b) `(my-and ,a ,b))
+        (defmacro my-if (test then else) `(cond (,test ,then) ('t ,else)))
+        (defmacro defconst (name val) `(defun ,name () ,val))").unwrap();
+
+    let mac = interp.get("my-and").unwrap();
+    assert!( mac.is_macro() );
+    assert_eq!(format!("{}", mac), "#<macro my-and>");
+
+    // arguments are passed unevaluated, so the second one is never evaluated
+    // here
+    assert!( interp.eval_str("(my-and '() (undefined))").unwrap().is_empty_list() );
+    assert!( interp.eval_str("(my-and-too 't 'yes)").unwrap().eq(&make_atom("yes")) );
+    interp.eval_str("(defconst five 5)").unwrap();
+    assert_eq!(format!("{}", interp.eval_str("(five)").unwrap()), "5");
+
+    // expansions are evaluated in tail position
+    let res = interp.eval_str("
+        (defun count-down (n) (my-if (= n 0) 'done (count-down (- n 1))))
+        (count-down 5000)");
+    assert!( res.unwrap().eq(&make_atom("done")) );
+
+    check(&mut interp, "(macroexpand-1 '(my-and x y))", "(cond (x y) ((quote t) (quote ())))");
+    check(&mut interp, "(macroexpand-1 '(my-and-too x y))", "(my-and x y)");
+    check(&mut interp, "(macroexpand '(my-and-too x y))", "(cond (x y) ((quote t) (quote ())))");
+    // subexpressions are not expanded
+    check(&mut interp, "(macroexpand '(my-if (my-and a b) c d))",
+          "(cond ((my-and a b) c) ((quote t) d))");
+    // anything else is left as it is
+    check(&mut interp, "(macroexpand '(cons a b))", "(cons a b)");
+    check(&mut interp, "(macroexpand-1 'my-and)", "my-and");
+
+    match interp.eval_str("(my-and 'a)") {
+        Err(Error::ArityMismatch { ref name, expected: Arity::Exactly(2), actual: 1, .. }) => assert_eq!(name, "my-and"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match interp.eval_str("(defmacro m x x)") {
+        Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "defmacro"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn test_syntax_rules() {
+    let mut interp = Interpreter::new();
+
+    interp.eval_str("
+        (define-syntax my-list
+          (syntax-rules ()
+            ((_) '())
+            ((_ x rest ...) (cons x (my-list rest ...)))))
+        (define-syntax swap-pairs
+          (syntax-rules ()
+            ((_ (a b ...) ...) '((b ... a) ...))))
+        (define-syntax last
+          (syntax-rules ()
+            ((_ x ... y) 'y)))
+        (define-syntax my-cond
+          (syntax-rules (else)
+            ((_ (else e)) e)
+            ((_ (c e) clause ...) (cond (c e) ('t (my-cond clause ...))))))").unwrap();
+
+    assert!( interp.get("my-list").unwrap().is_syntax() );
+    assert_eq!(format!("{}", interp.get("my-list").unwrap()), "#<syntax my-list>");
+
+    // ellipses, in nested patterns and before the end of a pattern
+    check(&mut interp, "(my-list 1 (+ 1 1) 3)", "(1 2 3)");
+    check(&mut interp, "(my-list)", "()");
+    check(&mut interp, "(swap-pairs (1 2 3) (4) (5 6))", "((2 3 1) (4) (6 5))");
+    check(&mut interp, "(last a b c)", "c");
+
+    // literals only match themselves
+    check(&mut interp, "(my-cond ((eq 'a 'b) 1) ((eq 'a 'a) 2) (else 3))", "2");
+    check(&mut interp, "(my-cond ((eq 'a 'b) 1) (else 3))", "3");
+    assert!( interp.eval_str("(my-cond (other 3))").is_err() );
+
+    check(&mut interp, "(macroexpand-1 '(my-list 1 2))", "(cons 1 (my-list 2))");
+    check(&mut interp, "(macroexpand '(my-cond (else 'x)))", "(quote x)");
+
+    match interp.eval_str("(last)") {
+        Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "last"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!( interp.eval_str("(define-syntax bad (syntax-rules () ((_ ... x) x)))").is_err() );
+    assert!( interp.eval_str("(define-syntax bad (syntax-rules (1) ((_ x) x)))").is_err() );
+    assert!( interp.eval_str("(define-syntax bad (lambda (x) x))").is_err() );
+    // a pattern variable may only appear once in a pattern
+    for src in &["(define-syntax d (syntax-rules () ((_ (a a) ...) 'x)))",
+                 "(define-syntax d (syntax-rules () ((_ a (b a)) 'x)))"] {
+        match interp.eval_str(src) {
+            Err(Error::BadForm { ref form, .. }) => assert_eq!(form, "syntax-rules"),
+            res => panic!("unexpected result for {}: {:?}", src, res),
+        }
+    }
+    assert!( interp.get("d").is_none() );
+    interp.eval_str("(define-syntax bad (syntax-rules () ((_ x ...) x)))").unwrap();
+    assert!( interp.eval_str("(bad 1 2)").is_err() );
+}
+
+#[test]
+fn test_syntax_rules_hygiene() {
+    let mut interp = Interpreter::new();
+
+    // the macro binds `v`, which doesn't capture the user's `v`
+    interp.eval_str("
+        (define-syntax my-or
+          (syntax-rules ()
+            ((_ a b) ((lambda (v) (cond ((eq v '()) b) ('t v))) a))))").unwrap();
+    check(&mut interp, "((lambda (v) (my-or '() v)) 'users)", "users");
+    check(&mut interp, "(my-or 'first (undefined))", "first");
+
+    // the macro's `cons` is the global one, even where the user has bound
+    // `cons` to something else
+    interp.eval_str("
+        (define-syntax pair
+          (syntax-rules ()
+            ((_ a b) (cons a (cons b '())))))").unwrap();
+    check(&mut interp, "((lambda (cons) (pair cons 2)) 1)", "(1 2)");
+
+    // quoted symbols from templates are just symbols
+    interp.eval_str("(define-syntax name-of (syntax-rules () ((_) 'result)))").unwrap();
+    assert!( interp.eval_str("(name-of)").unwrap().eq(&make_atom("result")) );
+    assert!( interp.eval_str("(eq (name-of) 'result)").unwrap().eq(&make_atom("t")) );
+
+    // a macro that expands into a use of itself, binding the same name each time
+    interp.eval_str("
+        (define-syntax nest
+          (syntax-rules ()
+            ((_ e) e)
+            ((_ e more ...) ((lambda (x) (cons x (nest more ...))) e))))").unwrap();
+    check(&mut interp, "((lambda (x) (nest 1 x (+ x 1) '())) 10)", "(1 10 11)");
+
+    // the reader won't produce the symbols macros introduce
+    assert!( read("foo\u{0}1").is_err() );
+
+    // so they are renamed when the source of a function is printed: those
+    // the function binds to fresh symbols, and the others to the symbols
+    // they were made from
+    interp.eval_str("
+        (define-syntax def-adder
+          (syntax-rules ()
+            ((_ name n) (defun name (x) (let ((y n)) (+ x y))))))
+        (def-adder add-x x)
+        (define x 10)").unwrap();
+    let src = format!("{}", unwrap_closure(interp.get("add-x").unwrap()).defun().unwrap());
+    assert_eq!(src, "(defun add-x (x-1) (let ((y x)) (+ x-1 y)))");
+    let mut fresh = Interpreter::new();
+    fresh.eval_str(&src).unwrap();
+    fresh.eval_str("(define x 10)").unwrap();
+    check(&mut fresh, "(add-x 1)", "11");
+    check(&mut interp, "(add-x 1)", "11");
+
+    // as are those in the expressions errors are about
+    interp.eval_str("(define-syntax bad-if (syntax-rules () ((_) (if))))").unwrap();
+    let msg = format!("{}", interp.eval_str("(bad-if)").unwrap_err());
+    assert_eq!(msg, "Invalid `if`: expected (if test then else) or (if test then): (if)");
+}